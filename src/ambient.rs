@@ -0,0 +1,151 @@
+use std::convert::Infallible;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use actix_web::body::{BodySize, MessageBody};
+use actix_web::rt::time::{sleep, Instant, Sleep};
+use actix_web::web::{self, Bytes};
+use actix_web::HttpResponse;
+use serde_json::json;
+
+use crate::auth::Profile;
+use crate::{choose_movies, find_channel, grid_tile, ChannelQuery, Data};
+
+/// Browsers reconnect after this many milliseconds, e.g. when the server restarts.
+const RETRY_MS: u64 = 5000;
+
+/// Server-sent events with a new grid tile every `ambient_interval` seconds.
+struct TileEvents {
+    data: Arc<Data>,
+    profile: Profile,
+    channel: Option<String>,
+    sleep: Pin<Box<Sleep>>,
+    started: bool,
+}
+
+impl TileEvents {
+    fn interval(&self) -> Duration {
+        Duration::from_secs(self.data.config().ambient_interval)
+    }
+
+    /// One `tile` event, a comment if the channel has no movies to show.
+    fn next_event(&self) -> Bytes {
+        let channel = find_channel(&self.data, &self.channel);
+        let tile = choose_movies(&self.data, &self.profile, channel.as_ref(), 1)
+            .first()
+            .map(|m| grid_tile(&self.data, &self.profile, m))
+            .filter(|tile| !tile.is_empty());
+        match tile {
+            // json keeps newlines in file names from ending the event
+            Some(tile) => Bytes::from(format!(
+                "event: tile\ndata: {}\n\n",
+                json!({ "html": tile })
+            )),
+            None => Bytes::from_static(b": nothing to show\n\n"),
+        }
+    }
+}
+
+impl MessageBody for TileEvents {
+    type Error = Infallible;
+
+    fn size(&self) -> BodySize {
+        BodySize::Stream
+    }
+
+    fn poll_next(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Bytes, Self::Error>>> {
+        let this = self.get_mut();
+        if !this.started {
+            this.started = true;
+            return Poll::Ready(Some(Ok(Bytes::from(format!("retry: {}\n\n", RETRY_MS)))));
+        }
+        if this.sleep.as_mut().poll(cx).is_pending() {
+            return Poll::Pending;
+        }
+        // read every time, the interval can change on reload
+        let deadline = Instant::now() + this.interval();
+        this.sleep.as_mut().reset(deadline);
+        Poll::Ready(Some(Ok(this.next_event())))
+    }
+}
+
+/// `/grid/events`, the tiles the ambient grid swaps in.
+pub async fn events(
+    data: web::Data<Arc<Data>>,
+    profile: Profile,
+    query: web::Query<ChannelQuery>,
+) -> HttpResponse {
+    let data = data.get_ref().clone();
+    let interval = Duration::from_secs(data.config().ambient_interval);
+    let body = TileEvents {
+        data,
+        profile,
+        channel: query.into_inner().channel,
+        sleep: Box::pin(sleep(interval)),
+        started: false,
+    };
+    HttpResponse::Ok()
+        .content_type("text/event-stream")
+        .insert_header(("Cache-Control", "no-cache"))
+        // no buffering in nginx
+        .insert_header(("X-Accel-Buffering", "no"))
+        .body(body)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::{test_data, test_movie};
+    use std::path::PathBuf;
+
+    fn events(movies: Vec<crate::Movie>) -> TileEvents {
+        TileEvents {
+            data: Arc::new(test_data(movies, Default::default())),
+            profile: Profile(crate::auth::DEFAULT_PROFILE.to_string()),
+            channel: None,
+            sleep: Box::pin(sleep(Duration::ZERO)),
+            started: false,
+        }
+    }
+
+    async fn next(body: &mut TileEvents) -> String {
+        let chunk = std::future::poll_fn(|cx| Pin::new(&mut *body).poll_next(cx)).await;
+        String::from_utf8(chunk.unwrap().unwrap().to_vec()).unwrap()
+    }
+
+    #[actix_web::test]
+    async fn sends_tiles_as_events() {
+        let mut movie = test_movie("A");
+        movie.info.title = Some("Two\nlines".to_string());
+        movie.poster = vec![PathBuf::from("A/A-poster.jpg")];
+        let mut body = events(vec![movie]);
+        assert_eq!(next(&mut body).await, "retry: 5000\n\n");
+
+        let event = next(&mut body).await;
+        let data = event
+            .strip_prefix("event: tile\ndata: ")
+            .and_then(|e| e.strip_suffix("\n\n"))
+            .unwrap();
+        // a single data line, the newline of the title is escaped
+        assert!(!data.contains('\n'));
+        let html = serde_json::from_str::<serde_json::Value>(data).unwrap()["html"]
+            .as_str()
+            .unwrap()
+            .to_string();
+        assert!(html.contains(r#"href="/details/A/A.mp4""#), "{}", html);
+        assert!(html.contains("Two\nlines"));
+    }
+
+    #[actix_web::test]
+    async fn comments_when_nothing_is_left() {
+        let mut body = events(Vec::new());
+        next(&mut body).await;
+        assert_eq!(next(&mut body).await, ": nothing to show\n\n");
+    }
+}
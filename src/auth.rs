@@ -0,0 +1,715 @@
+use std::fs::read_to_string;
+use std::future::{ready, Ready};
+use std::io;
+use std::path::Path;
+use std::sync::{Arc, RwLock};
+
+use actix_web::body::BoxBody;
+use actix_web::cookie::{time, Cookie, SameSite};
+use actix_web::dev::{Payload, ServiceRequest, ServiceResponse};
+use actix_web::http::header;
+use actix_web::middleware::Next;
+use actix_web::{web, Error, FromRequest, HttpMessage, HttpRequest, HttpResponse, Responder};
+use argon2::password_hash::phc::PasswordHash;
+use argon2::password_hash::{PasswordHasher, PasswordVerifier};
+use argon2::Argon2;
+use base64::Engine;
+use hmac::{Hmac, KeyInit, Mac};
+use percent_encoding::percent_decode_str;
+use rand::Rng;
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+
+use crate::dlna::in_networks;
+use crate::parental::certification_age;
+use crate::store::Store;
+use crate::{escape_html, Data};
+
+pub const SESSION_COOKIE: &str = "rvs_session";
+pub const TOKEN_COOKIE: &str = "rvs_token";
+/// Profile used when authentication is disabled.
+pub const DEFAULT_PROFILE: &str = "default";
+const SESSION_DAYS: i64 = 30;
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct User {
+    pub name: String,
+    /// Argon2 PHC string, see `--hash-password`.
+    pub password: String,
+    /// Overrides the global `max_certification` for this user.
+    pub max_certification: Option<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct UsersFile {
+    #[serde(default)]
+    users: Vec<User>,
+}
+
+/// Users from the users file, their sessions are kept in the [`Store`].
+#[derive(Debug)]
+pub struct Auth {
+    users: RwLock<Vec<User>>,
+    store: Arc<Store>,
+}
+
+pub fn hash_password(password: &str) -> Result<String, String> {
+    Argon2::default()
+        .hash_password(password.as_bytes())
+        .map(|h| h.to_string())
+        .map_err(|e| e.to_string())
+}
+
+fn verify_password(password: &str, hash: &str) -> bool {
+    PasswordHash::new(hash)
+        .map(|h| {
+            Argon2::default()
+                .verify_password(password.as_bytes(), &h)
+                .is_ok()
+        })
+        .unwrap_or(false)
+}
+
+fn read_users(path: &Path) -> io::Result<Vec<User>> {
+    let content = read_to_string(path)?;
+    let file: UsersFile = toml::from_str(&content).map_err(|e| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("{}: {}", path.display(), e),
+        )
+    })?;
+    // a typo would lift the limit, the user would see everything
+    for user in &file.users {
+        if let Some(max) = &user.max_certification {
+            if certification_age(max).is_none() {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!(
+                        "{}: users.{}.max_certification: unknown certification {}",
+                        path.display(),
+                        user.name,
+                        max
+                    ),
+                ));
+            }
+        }
+    }
+    Ok(file.users)
+}
+
+impl Auth {
+    pub fn load(path: &Path, store: Arc<Store>) -> io::Result<Auth> {
+        let users = read_users(path)?;
+        store.retain_sessions(|name| users.iter().any(|u| u.name == name));
+        Ok(Auth {
+            users: RwLock::new(users),
+            store,
+        })
+    }
+
+    /// Rereads the users file, sessions of removed users end.
+    pub fn reload(&self, path: &Path) -> io::Result<()> {
+        let users = read_users(path)?;
+        self.store
+            .retain_sessions(|name| users.iter().any(|u| u.name == name));
+        *self.users.write().unwrap() = users;
+        Ok(())
+    }
+
+    pub fn user(&self, name: &str) -> Option<User> {
+        self.users
+            .read()
+            .unwrap()
+            .iter()
+            .find(|u| u.name == name)
+            .cloned()
+    }
+
+    pub fn names(&self) -> Vec<String> {
+        self.users
+            .read()
+            .unwrap()
+            .iter()
+            .map(|u| u.name.clone())
+            .collect()
+    }
+
+    pub fn verify(&self, name: &str, password: &str) -> bool {
+        match self.user(name) {
+            Some(user) => verify_password(password, &user.password),
+            None => false,
+        }
+    }
+
+    pub fn login(&self, name: &str) -> String {
+        let token: String = (0..32)
+            .map(|_| format!("{:02x}", rand::thread_rng().gen::<u8>()))
+            .collect();
+        let expires = unix_time() + SESSION_DAYS as u64 * 24 * 60 * 60;
+        self.store.add_session(&session_key(&token), name, expires);
+        token
+    }
+
+    pub fn logout(&self, token: &str) {
+        self.store.remove_session(&session_key(token));
+    }
+
+    pub fn session_user(&self, token: &str) -> Option<String> {
+        self.store.session_user(&session_key(token))
+    }
+}
+
+/// What the store keeps of a session cookie.
+fn session_key(token: &str) -> String {
+    Sha256::digest(token.as_bytes())
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+/// Signs `/movie`, `/image` and `/subtitle` urls so players and casting devices
+/// can fetch them without cookies or credentials.
+#[derive(Debug)]
+pub struct UrlSigner {
+    key: [u8; 32],
+    ttl: u64,
+}
+
+fn unix_time() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+impl UrlSigner {
+    /// Uses a random key, urls signed before a restart become invalid.
+    pub fn new(ttl: u64) -> UrlSigner {
+        UrlSigner {
+            key: rand::thread_rng().gen(),
+            ttl,
+        }
+    }
+
+    fn signature(&self, path: &str, profile: &str, expires: u64) -> Hmac<Sha256> {
+        let mut mac = Hmac::<Sha256>::new_from_slice(&self.key).expect("any key length works");
+        mac.update(path.as_bytes());
+        mac.update(b"\n");
+        mac.update(profile.as_bytes());
+        mac.update(b"\n");
+        mac.update(expires.to_string().as_bytes());
+        mac
+    }
+
+    /// Query string for the (not percent encoded) `path`, e.g. `/movie/a/a.mp4`.
+    pub fn sign(&self, path: &str, profile: &str) -> String {
+        let expires = unix_time() + self.ttl;
+        let signature = self
+            .signature(path, profile, expires)
+            .finalize()
+            .into_bytes();
+        format!(
+            "profile={}&expires={}&signature={}",
+            encode_query_value(profile),
+            expires,
+            signature
+                .iter()
+                .map(|b| format!("{:02x}", b))
+                .collect::<String>()
+        )
+    }
+
+    /// The profile a signed url was issued for.
+    pub fn verify(&self, req: &ServiceRequest) -> Option<Profile> {
+        if !is_media(req.path()) {
+            return None;
+        }
+        let query = web::Query::<SignedQuery>::from_query(req.query_string()).ok()?;
+        if query.expires < unix_time() {
+            return None;
+        }
+        let signature: Vec<u8> = (0..query.signature.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(query.signature.get(i..i + 2)?, 16).ok())
+            .collect::<Option<Vec<u8>>>()?;
+        let path = percent_decode_str(req.path()).decode_utf8().ok()?;
+        self.signature(&path, &query.profile, query.expires)
+            .verify_slice(&signature)
+            .ok()?;
+        Some(Profile(query.profile.clone()))
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct SignedQuery {
+    profile: String,
+    expires: u64,
+    signature: String,
+}
+
+fn is_media(path: &str) -> bool {
+    ["/movie/", "/image/", "/subtitle/"]
+        .iter()
+        .any(|p| path.starts_with(p))
+}
+
+/// Compares digests of both, so the time taken depends on neither content nor length.
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    let (a, b) = (Sha256::digest(a), Sha256::digest(b));
+    a.iter().zip(&b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+#[derive(Debug, Deserialize)]
+struct TokenQuery {
+    token: Option<String>,
+}
+
+/// Middleware that requires the shared `auth_token` or basic auth credentials from the config.
+pub async fn require_credentials(
+    req: ServiceRequest,
+    next: Next<BoxBody>,
+) -> Result<ServiceResponse<BoxBody>, Error> {
+    let data = match req.app_data::<web::Data<Arc<Data>>>() {
+        Some(data) => data.clone(),
+        None => return next.call(req).await,
+    };
+    let config = data.config();
+    let basic = match (&config.basic_auth_user, &config.basic_auth_password) {
+        (Some(user), Some(password)) => Some(format!("{}:{}", user, password)),
+        _ => None,
+    };
+    if (config.auth_token.is_none() && basic.is_none()) || is_public_metrics(&req) || is_dlna(&req)
+    {
+        return next.call(req).await;
+    }
+
+    let authorization = req
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|a| a.to_str().ok())
+        .unwrap_or_default()
+        .to_string();
+    let query_token = web::Query::<TokenQuery>::from_query(req.query_string())
+        .ok()
+        .and_then(|q| q.token.clone());
+    let token_ok = config.auth_token.as_ref().is_some_and(|token| {
+        let matches = |given: &str| constant_time_eq(given.as_bytes(), token.as_bytes());
+        authorization.strip_prefix("Bearer ").is_some_and(matches)
+            || req.cookie(TOKEN_COOKIE).is_some_and(|c| matches(c.value()))
+            || query_token.as_deref().is_some_and(matches)
+    });
+    let basic_ok = basic.as_ref().is_some_and(|basic| {
+        authorization
+            .strip_prefix("Basic ")
+            .and_then(|b| base64::engine::general_purpose::STANDARD.decode(b).ok())
+            .is_some_and(|given| constant_time_eq(&given, basic.as_bytes()))
+    });
+    let signed_ok = data
+        .signer
+        .as_ref()
+        .is_some_and(|s| s.verify(&req).is_some());
+
+    if token_ok || basic_ok || signed_ok {
+        let remember_token = token_ok && query_token.is_some();
+        let mut res = next.call(req).await?;
+        if remember_token {
+            // keep the browser unlocked after opening `/?token=...` once
+            if let Some(token) = &config.auth_token {
+                let cookie = Cookie::build(TOKEN_COOKIE, token.clone())
+                    .path("/")
+                    .secure(config.tls_cert.is_some())
+                    .http_only(true)
+                    .same_site(SameSite::Lax)
+                    .max_age(time::Duration::days(SESSION_DAYS))
+                    .finish();
+                res.response_mut().add_cookie(&cookie)?;
+            }
+        }
+        return Ok(res);
+    }
+
+    let mut res = HttpResponse::Unauthorized();
+    if basic.is_some() {
+        res.insert_header((
+            header::WWW_AUTHENTICATE,
+            r#"Basic realm="random_video_server", charset="UTF-8""#,
+        ));
+    }
+    Ok(req.into_response(res.body("credentials required")))
+}
+
+/// The profile a request acts for: the logged in user or [`DEFAULT_PROFILE`].
+#[derive(Debug, Clone)]
+pub struct Profile(pub String);
+
+impl FromRequest for Profile {
+    type Error = Error;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        ready(Ok(req
+            .extensions()
+            .get::<Profile>()
+            .cloned()
+            .unwrap_or_else(|| Profile(DEFAULT_PROFILE.to_string()))))
+    }
+}
+
+/// `/metrics` with `metrics_public` set, so Prometheus can scrape it without credentials.
+fn is_public_metrics(req: &ServiceRequest) -> bool {
+    req.path() == "/metrics"
+        && req
+            .app_data::<web::Data<Arc<Data>>>()
+            .is_some_and(|d| d.config().metrics_public)
+}
+
+/// The DLNA server for the clients in `dlna_networks`, TVs can't log in. The streams it
+/// lists are signed.
+fn is_dlna(req: &ServiceRequest) -> bool {
+    req.path().starts_with("/dlna/")
+        && req.app_data::<web::Data<Arc<Data>>>().is_some_and(|d| {
+            d.dlna.is_some()
+                && req
+                    .peer_addr()
+                    .is_some_and(|peer| in_networks(peer.ip(), &d.config().dlna_networks))
+        })
+}
+
+/// Middleware that requires a session cookie when authentication is enabled.
+pub async fn require_login(
+    req: ServiceRequest,
+    next: Next<BoxBody>,
+) -> Result<ServiceResponse<BoxBody>, Error> {
+    let auth = req
+        .app_data::<web::Data<Arc<Data>>>()
+        .and_then(|d| d.auth.clone());
+    let auth = match auth {
+        Some(auth) => auth,
+        None => return next.call(req).await,
+    };
+    if req.path() == "/login" || is_public_metrics(&req) || is_dlna(&req) {
+        return next.call(req).await;
+    }
+    let signed = req
+        .app_data::<web::Data<Arc<Data>>>()
+        .and_then(|d| d.signer.as_ref())
+        .and_then(|s| s.verify(&req));
+    if let Some(profile) = signed {
+        req.extensions_mut().insert(profile);
+        return next.call(req).await;
+    }
+    if let Some(user) = req
+        .cookie(SESSION_COOKIE)
+        .and_then(|c| auth.session_user(c.value()))
+    {
+        req.extensions_mut().insert(Profile(user));
+        return next.call(req).await;
+    }
+
+    let wants_html = req
+        .headers()
+        .get(header::ACCEPT)
+        .and_then(|a| a.to_str().ok())
+        .is_some_and(|a| a.contains("text/html"));
+    let res = if wants_html {
+        HttpResponse::SeeOther()
+            .insert_header((
+                header::LOCATION,
+                format!("/login?next={}", encode_query_value(&req.uri().to_string())),
+            ))
+            .finish()
+    } else {
+        HttpResponse::Unauthorized().body("login required")
+    };
+    Ok(req.into_response(res))
+}
+
+fn encode_query_value(s: &str) -> String {
+    s.bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' | b'/' => {
+                (b as char).to_string()
+            }
+            _ => format!("%{:02X}", b),
+        })
+        .collect()
+}
+
+/// Only allow redirects within this server.
+fn safe_next(next: &Option<String>) -> String {
+    match next {
+        // browsers read `/\host` like `//host` and drop tabs and newlines in urls
+        Some(n)
+            if n.starts_with('/')
+                && !n.starts_with("//")
+                && !n.starts_with("/\\")
+                && !n.chars().any(|c| c.is_control()) =>
+        {
+            n.clone()
+        }
+        _ => "/".to_string(),
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct LoginQuery {
+    next: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct LoginForm {
+    name: String,
+    password: String,
+    next: Option<String>,
+}
+
+fn login_page(next: &str, error: Option<&str>) -> String {
+    format!(
+        r#"<!DOCTYPE html>
+            <html lang="en">
+            <head>
+            <meta charset="UTF-8">
+            <meta name="viewport" content="width=device-width, initial-scale=1.0">
+            <title>Login</title>
+            </head>
+            <body>
+            <div class="container">
+            <h1>Login</h1>
+            {}
+            <form method="post" action="/login">
+            <input type="hidden" name="next" value="{}">
+            <p><input name="name" placeholder="Name" autofocus></p>
+            <p><input name="password" type="password" placeholder="Password"></p>
+            <p><button type="submit">Login</button></p>
+            </form>
+            </div>
+            </body>
+            </html>"#,
+        error
+            .map(|e| format!("<p>{}</p>", escape_html(e)))
+            .unwrap_or_default(),
+        escape_html(next)
+    )
+}
+
+pub async fn login_form(query: web::Query<LoginQuery>) -> impl Responder {
+    HttpResponse::Ok()
+        .content_type("text/html")
+        .body(login_page(&safe_next(&query.next), None))
+}
+
+pub async fn login(data: web::Data<Arc<Data>>, form: web::Form<LoginForm>) -> impl Responder {
+    let next = safe_next(&form.next);
+    let auth = match &data.auth {
+        Some(auth) => auth.clone(),
+        None => {
+            return HttpResponse::SeeOther()
+                .insert_header((header::LOCATION, next))
+                .finish()
+        }
+    };
+    let form = form.into_inner();
+    let name = form.name.clone();
+    let verified = {
+        let auth = auth.clone();
+        web::block(move || auth.verify(&form.name, &form.password))
+            .await
+            .unwrap_or(false)
+    };
+    if !verified {
+        return HttpResponse::Unauthorized()
+            .content_type("text/html")
+            .body(login_page(&next, Some("Wrong name or password")));
+    }
+    let cookie = Cookie::build(SESSION_COOKIE, auth.login(&name))
+        .path("/")
+        .secure(data.config().tls_cert.is_some())
+        .http_only(true)
+        .same_site(SameSite::Lax)
+        .max_age(time::Duration::days(SESSION_DAYS))
+        .finish();
+    HttpResponse::SeeOther()
+        .cookie(cookie)
+        .insert_header((header::LOCATION, next))
+        .finish()
+}
+
+pub async fn logout(data: web::Data<Arc<Data>>, req: HttpRequest) -> impl Responder {
+    if let (Some(auth), Some(cookie)) = (&data.auth, req.cookie(SESSION_COOKIE)) {
+        auth.logout(cookie.value());
+    }
+    let mut cookie = Cookie::build(SESSION_COOKIE, "").path("/").finish();
+    cookie.make_removal();
+    HttpResponse::SeeOther()
+        .cookie(cookie)
+        .insert_header((header::LOCATION, "/login"))
+        .finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::test::TestRequest;
+
+    fn request(path: &str, query: &str) -> ServiceRequest {
+        TestRequest::with_uri(&format!("{}?{}", path, query)).to_srv_request()
+    }
+
+    #[test]
+    fn signed_urls() {
+        let signer = UrlSigner::new(60);
+        let query = signer.sign("/movie/A b/A b.mp4", "alice");
+        let profile = signer.verify(&request("/movie/A%20b/A%20b.mp4", &query));
+        assert_eq!(profile.map(|p| p.0), Some("alice".to_string()));
+
+        // another path, profile or key
+        assert!(signer.verify(&request("/movie/C/C.mp4", &query)).is_none());
+        let bob = query.replace("profile=alice", "profile=bob");
+        assert!(signer
+            .verify(&request("/movie/A%20b/A%20b.mp4", &bob))
+            .is_none());
+        assert!(UrlSigner::new(60)
+            .verify(&request("/movie/A%20b/A%20b.mp4", &query))
+            .is_none());
+        // only media routes
+        let query = signer.sign("/details/A b/A b.mp4", "alice");
+        assert!(signer
+            .verify(&request("/details/A%20b/A%20b.mp4", &query))
+            .is_none());
+    }
+
+    #[test]
+    fn signed_urls_expire() {
+        let signer = UrlSigner::new(60);
+        let expires = unix_time() - 1;
+        let signature: String = signer
+            .signature("/image/A/A-poster.jpg", "alice", expires)
+            .finalize()
+            .into_bytes()
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect();
+        let query = format!("profile=alice&expires={}&signature={}", expires, signature);
+        assert!(signer
+            .verify(&request("/image/A/A-poster.jpg", &query))
+            .is_none());
+        // a longer lifetime needs a new signature
+        let query = query.replace(&expires.to_string(), &(expires + 120).to_string());
+        assert!(signer
+            .verify(&request("/image/A/A-poster.jpg", &query))
+            .is_none());
+    }
+
+    #[test]
+    fn compares_tokens() {
+        assert!(constant_time_eq(b"secret", b"secret"));
+        assert!(!constant_time_eq(b"secret", b"secreT"));
+        assert!(!constant_time_eq(b"secret", b"secret2"));
+        assert!(!constant_time_eq(b"", b"secret"));
+    }
+
+    #[test]
+    fn redirects_only_within_the_server() {
+        let next = |n: &str| safe_next(&Some(n.to_string()));
+        assert_eq!(next("/grid?channel=kids"), "/grid?channel=kids");
+        assert_eq!(next("/details/A/A.mp4"), "/details/A/A.mp4");
+        for outside in [
+            "//evil.com",
+            "/\\evil.com",
+            "/\t/evil.com",
+            "/\n/evil.com",
+            "https://evil.com",
+            "evil.com",
+            "",
+        ] {
+            assert_eq!(next(outside), "/", "{:?}", outside);
+        }
+        assert_eq!(safe_next(&None), "/");
+    }
+
+    #[test]
+    fn sessions_survive_restarts() {
+        let dir = tempfile::tempdir().unwrap();
+        let users = dir.path().join("users.toml");
+        let user = |name: &str| {
+            format!(
+                "[[users]]\nname = \"{}\"\npassword = \"{}\"\n",
+                name,
+                hash_password("secret").unwrap()
+            )
+        };
+        std::fs::write(&users, user("alice") + &user("bob")).unwrap();
+        let state = dir.path().join("state.json");
+
+        let store = Arc::new(Store::open(state.clone()).unwrap());
+        let auth = Auth::load(&users, store.clone()).unwrap();
+        assert!(auth.verify("alice", "secret"));
+        assert!(!auth.verify("alice", "wrong"));
+        let alice = auth.login("alice");
+        let bob = auth.login("bob");
+        let gone = auth.login("alice");
+        auth.logout(&gone);
+        assert_eq!(auth.session_user(&alice).as_deref(), Some("alice"));
+        assert_eq!(auth.session_user(&gone), None);
+        store.flush().unwrap();
+        // only hashes of the cookies are written
+        let written = std::fs::read_to_string(&state).unwrap();
+        assert!(!written.contains(&alice));
+
+        let store = Arc::new(Store::open(state).unwrap());
+        let auth = Auth::load(&users, store).unwrap();
+        assert_eq!(auth.session_user(&alice).as_deref(), Some("alice"));
+        assert_eq!(auth.session_user(&bob).as_deref(), Some("bob"));
+        assert_eq!(auth.session_user("other"), None);
+
+        // removing a user from the file ends their sessions
+        std::fs::write(&users, user("alice")).unwrap();
+        auth.reload(&users).unwrap();
+        assert_eq!(auth.session_user(&alice).as_deref(), Some("alice"));
+        assert_eq!(auth.session_user(&bob), None);
+    }
+
+    #[actix_web::test]
+    async fn metrics_need_credentials_unless_public() {
+        use actix_web::{middleware, test, App};
+
+        let dir = tempfile::tempdir().unwrap();
+        let users = dir.path().join("users.toml");
+        std::fs::write(
+            &users,
+            format!(
+                "[[users]]\nname = \"alice\"\npassword = \"{}\"\n",
+                hash_password("secret").unwrap()
+            ),
+        )
+        .unwrap();
+        for metrics_public in [false, true] {
+            let config = crate::config::Config {
+                auth_token: Some("token".to_string()),
+                metrics_public,
+                ..Default::default()
+            };
+            let data = crate::tests::test_data(Vec::new(), config);
+            let data = Data {
+                auth: Some(Arc::new(Auth::load(&users, data.store.clone()).unwrap())),
+                ..data
+            };
+            let app = test::init_service(
+                App::new()
+                    .app_data(web::Data::new(Arc::new(data)))
+                    .wrap(middleware::from_fn(require_login))
+                    .wrap(middleware::from_fn(require_credentials))
+                    .route("/metrics", web::get().to(HttpResponse::Ok))
+                    .route("/", web::get().to(HttpResponse::Ok)),
+            )
+            .await;
+            let status = |uri: &'static str| {
+                let req = test::TestRequest::with_uri(uri).to_request();
+                let app = &app;
+                async move { test::call_service(app, req).await.status() }
+            };
+            assert_eq!(status("/metrics").await.is_success(), metrics_public);
+            assert_eq!(status("/").await, actix_web::http::StatusCode::UNAUTHORIZED);
+        }
+    }
+}
@@ -0,0 +1,763 @@
+use std::fs::read_to_string;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use clap::Args;
+use serde::{Deserialize, Serialize};
+use tracing_subscriber::EnvFilter;
+
+use crate::dlna::parse_network;
+use crate::ensure_trailing_slash;
+use crate::files::SymlinkPolicy;
+use crate::logging::LogFormat;
+use crate::naming::Naming;
+use crate::parental::certification_age;
+use crate::store::Store;
+
+/// The effective configuration: defaults < config file < `RVS_*` environment < command line.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub directory: String,
+    pub ip_bind: String,
+    pub port_bind: u16,
+    pub trailer_factor: i8,
+    pub poster_factor: i8,
+    pub thumb_factor: i8,
+    pub fanart_factor: i8,
+    pub video_factor: i8,
+    pub extra_factor: i8,
+    pub clearlogo_factor: i8,
+    pub clearart_factor: i8,
+    pub banner_factor: i8,
+    pub landscape_factor: i8,
+    pub disc_factor: i8,
+    pub state_file: PathBuf,
+    pub prefer_unwatched: bool,
+    pub favorite_factor: i8,
+    pub disliked_factor: i8,
+    pub users_file: Option<PathBuf>,
+    pub max_certification: Option<String>,
+    pub allow_unrated: bool,
+    pub auth_token: Option<String>,
+    pub basic_auth_user: Option<String>,
+    pub basic_auth_password: Option<String>,
+    pub signed_url_ttl: u64,
+    pub tls_cert: Option<PathBuf>,
+    pub tls_key: Option<PathBuf>,
+    pub http_redirect_port: Option<u16>,
+    pub symlinks: SymlinkPolicy,
+    pub naming: Naming,
+    pub log_level: String,
+    pub log_format: LogFormat,
+    /// `/metrics` without login or credentials, for scrapers in a trusted network.
+    pub metrics_public: bool,
+    pub kodi_url: Option<String>,
+    pub kodi_user: Option<String>,
+    pub kodi_password: Option<String>,
+    pub kodi_path_prefix: Option<String>,
+    pub dlna: bool,
+    pub dlna_name: String,
+    /// Clients that browse the DLNA server without credentials.
+    pub dlna_networks: Vec<String>,
+    pub ambient_interval: u64,
+    // last, tables have to follow the plain values in toml
+    pub channels: Vec<Channel>,
+}
+
+impl Default for Config {
+    fn default() -> Config {
+        Config {
+            directory: String::new(),
+            ip_bind: "127.0.0.1".to_string(),
+            port_bind: 3070,
+            trailer_factor: 1,
+            poster_factor: 1,
+            thumb_factor: 1,
+            fanart_factor: 1,
+            video_factor: 0,
+            extra_factor: 0,
+            clearlogo_factor: 0,
+            clearart_factor: 0,
+            banner_factor: 0,
+            landscape_factor: 0,
+            disc_factor: 0,
+            state_file: Store::default_path().unwrap_or_default(),
+            prefer_unwatched: false,
+            favorite_factor: 4,
+            disliked_factor: 4,
+            users_file: None,
+            max_certification: None,
+            allow_unrated: false,
+            auth_token: None,
+            basic_auth_user: None,
+            basic_auth_password: None,
+            signed_url_ttl: 21600,
+            tls_cert: None,
+            tls_key: None,
+            http_redirect_port: None,
+            symlinks: SymlinkPolicy::default(),
+            naming: Naming::default(),
+            log_level: "info".to_string(),
+            log_format: LogFormat::default(),
+            metrics_public: false,
+            kodi_url: None,
+            kodi_user: None,
+            kodi_password: None,
+            kodi_path_prefix: None,
+            dlna: false,
+            dlna_name: "Random Video Server".to_string(),
+            dlna_networks: Vec::new(),
+            ambient_interval: 10,
+            channels: Vec::new(),
+        }
+    }
+}
+
+/// A named selection of the library, e.g. `/tv?channel=kids`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Channel {
+    pub name: String,
+    #[serde(default)]
+    pub genres: Vec<String>,
+    pub max_certification: Option<String>,
+}
+
+/// Command line options, each one can also be set as `RVS_<OPTION>`. Unset options
+/// are left out when serialized so they don't override the config file.
+#[derive(Debug, Clone, Default, Serialize, Args)]
+pub struct ConfigArgs {
+    #[arg(short, long, env = "RVS_DIRECTORY", help = "Kodi videos directory")]
+    directory: Option<String>,
+
+    #[arg(
+        short,
+        long,
+        env = "RVS_IP_BIND",
+        help = "IP for bind. 127.0.0.1 for only same machine. 0.0.0.0 for global access (default: 127.0.0.1)"
+    )]
+    ip_bind: Option<String>,
+
+    #[arg(
+        short,
+        long,
+        env = "RVS_PORT_BIND",
+        help = "Port for bind (default: 3070)"
+    )]
+    port_bind: Option<u16>,
+
+    #[arg(
+        long,
+        env = "RVS_TRAILER_FACTOR",
+        help = "Show trailers N-times more likely (default: 1)"
+    )]
+    trailer_factor: Option<i8>,
+    #[arg(
+        long,
+        env = "RVS_POSTER_FACTOR",
+        help = "Show posters N-times more likely (default: 1)"
+    )]
+    poster_factor: Option<i8>,
+    #[arg(
+        long,
+        env = "RVS_THUMB_FACTOR",
+        help = "Show thumb N-times more likely (default: 1)"
+    )]
+    thumb_factor: Option<i8>,
+    #[arg(
+        long,
+        env = "RVS_FANART_FACTOR",
+        help = "Show fanart N-times more likely (default: 1)"
+    )]
+    fanart_factor: Option<i8>,
+    #[arg(
+        long,
+        env = "RVS_VIDEO_FACTOR",
+        help = "Show video N-times more likely (default: 0)"
+    )]
+    video_factor: Option<i8>,
+    #[arg(
+        long,
+        env = "RVS_EXTRA_FACTOR",
+        help = "Show extras (featurettes, behind the scenes, ...) N-times more likely (default: 0)"
+    )]
+    extra_factor: Option<i8>,
+    #[arg(
+        long,
+        env = "RVS_CLEARLOGO_FACTOR",
+        help = "Show clearlogos N-times more likely (default: 0)"
+    )]
+    clearlogo_factor: Option<i8>,
+    #[arg(
+        long,
+        env = "RVS_CLEARART_FACTOR",
+        help = "Show clearart N-times more likely (default: 0)"
+    )]
+    clearart_factor: Option<i8>,
+    #[arg(
+        long,
+        env = "RVS_BANNER_FACTOR",
+        help = "Show banners N-times more likely (default: 0)"
+    )]
+    banner_factor: Option<i8>,
+    #[arg(
+        long,
+        env = "RVS_LANDSCAPE_FACTOR",
+        help = "Show landscapes N-times more likely (default: 0)"
+    )]
+    landscape_factor: Option<i8>,
+    #[arg(
+        long,
+        env = "RVS_DISC_FACTOR",
+        help = "Show disc art N-times more likely (default: 0)"
+    )]
+    disc_factor: Option<i8>,
+    #[arg(
+        long,
+        env = "RVS_STATE_FILE",
+        help = "Path to the watch history file [default: $XDG_DATA_HOME/random_video_server/state.json]"
+    )]
+    state_file: Option<PathBuf>,
+    #[arg(
+        long,
+        env = "RVS_PREFER_UNWATCHED",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "true",
+        help = "Let the tv play unwatched videos first (default: false)"
+    )]
+    prefer_unwatched: Option<bool>,
+    #[arg(
+        long,
+        env = "RVS_FAVORITE_FACTOR",
+        help = "Show favorite movies N-times more likely (default: 4)"
+    )]
+    favorite_factor: Option<i8>,
+    #[arg(
+        long,
+        env = "RVS_DISLIKED_FACTOR",
+        help = "Show disliked movies N-times less likely (default: 4)"
+    )]
+    disliked_factor: Option<i8>,
+    #[arg(
+        long,
+        env = "RVS_USERS_FILE",
+        help = "Path to a users file, enables login with per user history and flags"
+    )]
+    users_file: Option<PathBuf>,
+    #[arg(
+        long,
+        env = "RVS_MAX_CERTIFICATION",
+        help = "Only show movies up to this certification, e.g. PG-13 or 'FSK 12' (default: no limit)"
+    )]
+    max_certification: Option<String>,
+    #[arg(
+        long,
+        env = "RVS_ALLOW_UNRATED",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "true",
+        help = "Show movies without certification when a limit is set (default: false)"
+    )]
+    allow_unrated: Option<bool>,
+    #[arg(
+        long,
+        env = "RVS_AUTH_TOKEN",
+        hide_env_values = true,
+        help = "Shared token required for every request, as bearer token or ?token= once"
+    )]
+    auth_token: Option<String>,
+    #[arg(
+        long,
+        env = "RVS_BASIC_AUTH_USER",
+        help = "User for basic auth required for every request"
+    )]
+    basic_auth_user: Option<String>,
+    #[arg(
+        long,
+        env = "RVS_BASIC_AUTH_PASSWORD",
+        hide_env_values = true,
+        help = "Password for basic auth required for every request"
+    )]
+    basic_auth_password: Option<String>,
+    #[arg(
+        long,
+        env = "RVS_SIGNED_URL_TTL",
+        help = "Seconds signed /movie and /image urls stay valid (default: 21600)"
+    )]
+    signed_url_ttl: Option<u64>,
+    #[arg(
+        long,
+        env = "RVS_TLS_CERT",
+        help = "PEM certificate chain, serves https together with --tls-key"
+    )]
+    tls_cert: Option<PathBuf>,
+    #[arg(
+        long,
+        env = "RVS_TLS_KEY",
+        help = "PEM private key, serves https together with --tls-cert"
+    )]
+    tls_key: Option<PathBuf>,
+    #[arg(
+        long,
+        env = "RVS_HTTP_REDIRECT_PORT",
+        help = "Port for a plain http listener that redirects to https (default: none)"
+    )]
+    http_redirect_port: Option<u16>,
+    #[arg(
+        long,
+        env = "RVS_SYMLINKS",
+        help = "How to treat symlinks in the library (default: within-root)"
+    )]
+    symlinks: Option<SymlinkPolicy>,
+    #[arg(
+        long,
+        env = "RVS_NAMING",
+        help = "File naming of the library root, auto detects it per movie folder (default: auto)"
+    )]
+    naming: Option<Naming>,
+    #[arg(
+        long,
+        env = "RVS_LOG_LEVEL",
+        help = "Log level or filter, e.g. debug or random_video_server=debug,access=warn (default: info)"
+    )]
+    log_level: Option<String>,
+    #[arg(long, env = "RVS_LOG_FORMAT", help = "Log format (default: text)")]
+    log_format: Option<LogFormat>,
+    #[arg(
+        long,
+        env = "RVS_METRICS_PUBLIC",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "true",
+        help = "Serve /metrics without login or credentials (default: false)"
+    )]
+    metrics_public: Option<bool>,
+    #[arg(
+        long,
+        env = "RVS_KODI_URL",
+        help = "Read the library from Kodi's JSON-RPC api instead of scanning, e.g. http://kodi:8080"
+    )]
+    kodi_url: Option<String>,
+    #[arg(long, env = "RVS_KODI_USER", help = "User of Kodi's web server")]
+    kodi_user: Option<String>,
+    #[arg(
+        long,
+        env = "RVS_KODI_PASSWORD",
+        hide_env_values = true,
+        help = "Password of Kodi's web server"
+    )]
+    kodi_password: Option<String>,
+    #[arg(
+        long,
+        env = "RVS_KODI_PATH_PREFIX",
+        help = "The library directory as Kodi sees it, e.g. smb://nas/movies/ (default: directory)"
+    )]
+    kodi_path_prefix: Option<String>,
+    #[arg(
+        long,
+        env = "RVS_DLNA",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "true",
+        help = "Announce the library as DLNA/UPnP media server on the local network (default: false)"
+    )]
+    dlna: Option<bool>,
+    #[arg(
+        long,
+        env = "RVS_DLNA_NAME",
+        help = "Name TVs show for the DLNA server (default: Random Video Server)"
+    )]
+    dlna_name: Option<String>,
+    #[arg(
+        long,
+        env = "RVS_DLNA_NETWORKS",
+        value_delimiter = ',',
+        help = "Networks whose TVs browse the DLNA server without login, token or basic auth, e.g. 192.168.1.0/24 (default: none)"
+    )]
+    dlna_networks: Option<Vec<String>>,
+    #[arg(
+        long,
+        env = "RVS_AMBIENT_INTERVAL",
+        help = "Seconds between tile swaps of the ambient grid (default: 10)"
+    )]
+    ambient_interval: Option<u64>,
+}
+
+fn invalid(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, message)
+}
+
+/// `$XDG_CONFIG_HOME/random_video_server/config.toml`
+pub fn default_path() -> Option<PathBuf> {
+    dirs::config_dir().map(|p| p.join("random_video_server").join("config.toml"))
+}
+
+/// Reads the config file, a missing file is only fine if none was given explicitly.
+fn read_file(path: Option<&Path>) -> io::Result<Option<(PathBuf, String)>> {
+    let path = match path {
+        Some(path) => path.to_path_buf(),
+        None => match default_path() {
+            Some(path) if path.exists() => path,
+            _ => {
+                // the path used by older versions
+                match dirs::config_dir().map(|p| p.join("rp").join("config.toml")) {
+                    Some(old) if old.exists() => {
+                        // logging is not set up before the config is read
+                        eprintln!(
+                            "warning: reading {}, please move it to {}",
+                            old.display(),
+                            default_path().unwrap_or_default().display()
+                        );
+                        old
+                    }
+                    _ => return Ok(None),
+                }
+            }
+        },
+    };
+    match read_to_string(&path) {
+        Ok(content) => Ok(Some((path, content))),
+        Err(e) => Err(io::Error::new(
+            e.kind(),
+            format!("{}: {}", path.display(), e),
+        )),
+    }
+}
+
+/// Overwrites the keys of `base` that `layer` sets.
+fn merge(base: &mut toml::Table, layer: toml::Table) {
+    for (key, value) in layer {
+        base.insert(key, value);
+    }
+}
+
+impl Config {
+    /// Layers the config file `path` and the command line / environment `args` over the defaults.
+    /// Returns the config and the file it was read from.
+    pub fn load(path: Option<&Path>, args: &ConfigArgs) -> io::Result<(Config, Option<PathBuf>)> {
+        let mut table = toml::Table::try_from(Config::default())
+            .map_err(|e| invalid(format!("defaults: {}", e)))?;
+
+        let file = read_file(path)?;
+        if let Some((path, content)) = &file {
+            // parsed as Config first for errors with the key and line
+            toml::from_str::<Config>(content)
+                .map_err(|e| invalid(format!("{}: {}", path.display(), e)))?;
+            let layer: toml::Table = toml::from_str(content)
+                .map_err(|e| invalid(format!("{}: {}", path.display(), e)))?;
+            merge(&mut table, layer);
+        }
+
+        let layer =
+            toml::Table::try_from(args).map_err(|e| invalid(format!("command line: {}", e)))?;
+        merge(&mut table, layer);
+
+        let mut config: Config = table
+            .try_into()
+            .map_err(|e: toml::de::Error| invalid(e.message().to_string()))?;
+        if !config.directory.is_empty() {
+            config.directory = ensure_trailing_slash(config.directory);
+        }
+        Ok((config, file.map(|(path, _)| path)))
+    }
+
+    /// Whether requests need a login, token or basic auth.
+    pub fn access_restricted(&self) -> bool {
+        self.users_file.is_some()
+            || self.auth_token.is_some()
+            || (self.basic_auth_user.is_some() && self.basic_auth_password.is_some())
+    }
+
+    /// All problems of the config, each starting with the key at fault.
+    pub fn validate(&self) -> Vec<String> {
+        let mut errors = Vec::new();
+        if self.directory.is_empty() {
+            errors.push("directory: not set".to_string());
+        } else if !Path::new(&self.directory).is_dir() {
+            errors.push(format!("directory: {} is not a directory", self.directory));
+        }
+        for (key, factor) in [
+            ("trailer_factor", self.trailer_factor),
+            ("poster_factor", self.poster_factor),
+            ("thumb_factor", self.thumb_factor),
+            ("fanart_factor", self.fanart_factor),
+            ("video_factor", self.video_factor),
+            ("extra_factor", self.extra_factor),
+            ("clearlogo_factor", self.clearlogo_factor),
+            ("clearart_factor", self.clearart_factor),
+            ("banner_factor", self.banner_factor),
+            ("landscape_factor", self.landscape_factor),
+            ("disc_factor", self.disc_factor),
+        ] {
+            if factor < 0 {
+                errors.push(format!("{}: must not be negative", key));
+            }
+        }
+        for (key, factor) in [
+            ("favorite_factor", self.favorite_factor),
+            ("disliked_factor", self.disliked_factor),
+        ] {
+            if factor < 1 {
+                errors.push(format!("{}: must be at least 1", key));
+            }
+        }
+        if self.state_file.as_os_str().is_empty() {
+            errors.push("state_file: no default data directory, please set it".to_string());
+        }
+        if let Some(users_file) = &self.users_file {
+            if !users_file.is_file() {
+                errors.push(format!("users_file: {} not found", users_file.display()));
+            }
+        }
+        if let Some(max) = &self.max_certification {
+            if certification_age(max).is_none() {
+                errors.push(format!("max_certification: unknown certification {}", max));
+            }
+        }
+        if self.basic_auth_user.is_some() != self.basic_auth_password.is_some() {
+            errors.push(
+                "basic_auth_user: basic_auth_user and basic_auth_password must be set together"
+                    .to_string(),
+            );
+        }
+        if self.signed_url_ttl == 0 {
+            errors.push("signed_url_ttl: must be at least 1".to_string());
+        }
+        match (&self.tls_cert, &self.tls_key) {
+            (Some(_), None) | (None, Some(_)) => {
+                errors.push("tls_cert: tls_cert and tls_key must be set together".to_string())
+            }
+            _ => {}
+        }
+        for (key, path) in [("tls_cert", &self.tls_cert), ("tls_key", &self.tls_key)] {
+            if let Some(path) = path {
+                if !path.is_file() {
+                    errors.push(format!("{}: {} not found", key, path.display()));
+                }
+            }
+        }
+        if let Some(port) = self.http_redirect_port {
+            if self.tls_cert.is_none() {
+                errors.push("http_redirect_port: only used with tls_cert and tls_key".to_string());
+            } else if port == self.port_bind {
+                errors.push("http_redirect_port: must differ from port_bind".to_string());
+            }
+        }
+        if let Err(e) = EnvFilter::try_new(&self.log_level) {
+            errors.push(format!("log_level: {}", e));
+        }
+        if let Some(url) = &self.kodi_url {
+            if !url.starts_with("http://") {
+                errors.push(format!("kodi_url: {} is not an http:// url", url));
+            }
+        } else if self.kodi_path_prefix.is_some() || self.kodi_user.is_some() {
+            errors.push("kodi_url: not set, needed by the other kodi options".to_string());
+        }
+        if self.kodi_password.is_some() && self.kodi_user.is_none() {
+            errors.push("kodi_password: needs kodi_user".to_string());
+        }
+        if self.ambient_interval == 0 {
+            errors.push("ambient_interval: must be at least 1".to_string());
+        }
+        if self.dlna && self.tls_cert.is_some() {
+            errors.push("dlna: TVs only stream over plain http, not with tls_cert".to_string());
+        }
+        for network in &self.dlna_networks {
+            if parse_network(network).is_none() {
+                errors.push(format!(
+                    "dlna_networks: {} is no address or network like 192.168.1.0/24",
+                    network
+                ));
+            }
+        }
+        for (i, channel) in self.channels.iter().enumerate() {
+            if channel.name.is_empty() {
+                errors.push(format!("channels[{}].name: must not be empty", i));
+            } else if self.channels[..i].iter().any(|c| c.name == channel.name) {
+                errors.push(format!(
+                    "channels[{}].name: {} is used twice",
+                    i, channel.name
+                ));
+            }
+            if let Some(max) = &channel.max_certification {
+                if certification_age(max).is_none() {
+                    errors.push(format!(
+                        "channels[{}].max_certification: unknown certification {}",
+                        i, max
+                    ));
+                }
+            }
+        }
+        errors
+    }
+
+    /// The config as toml with the secrets left out.
+    pub fn dump(&self) -> String {
+        let mut config = self.clone();
+        let redacted = Some("<redacted>".to_string());
+        if config.auth_token.is_some() {
+            config.auth_token = redacted.clone();
+        }
+        if config.basic_auth_password.is_some() {
+            config.basic_auth_password = redacted.clone();
+        }
+        if config.kodi_password.is_some() {
+            config.kodi_password = redacted;
+        }
+        toml::to_string(&config).unwrap_or_else(|e| format!("# {}", e))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::Parser;
+
+    #[derive(Parser)]
+    struct Cli {
+        #[command(flatten)]
+        options: ConfigArgs,
+    }
+
+    fn args(args: &[&str]) -> ConfigArgs {
+        Cli::try_parse_from(std::iter::once("rvs").chain(args.iter().copied()))
+            .unwrap()
+            .options
+    }
+
+    fn write(dir: &tempfile::TempDir, content: &str) -> PathBuf {
+        let path = dir.path().join("config.toml");
+        std::fs::write(&path, content).unwrap();
+        path
+    }
+
+    #[test]
+    fn layers_file_environment_and_command_line() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = write(
+            &dir,
+            "directory = \"/movies\"\nport_bind = 4000\nposter_factor = 2\nfanart_factor = 2\n\n\
+             [[channels]]\nname = \"kids\"\n",
+        );
+        // only this test sets these
+        std::env::set_var("RVS_FANART_FACTOR", "7");
+        std::env::set_var("RVS_THUMB_FACTOR", "5");
+        let args = args(&["--thumb-factor", "6", "--dlna"]);
+        std::env::remove_var("RVS_FANART_FACTOR");
+        std::env::remove_var("RVS_THUMB_FACTOR");
+
+        let (config, file) = Config::load(Some(&path), &args).unwrap();
+        assert_eq!(file, Some(path));
+        // defaults
+        assert_eq!(config.ip_bind, "127.0.0.1");
+        assert_eq!(config.trailer_factor, 1);
+        // file
+        assert_eq!(
+            config.directory,
+            format!("/movies{}", std::path::MAIN_SEPARATOR)
+        );
+        assert_eq!(config.port_bind, 4000);
+        assert_eq!(config.poster_factor, 2);
+        assert_eq!(config.channels[0].name, "kids");
+        // environment over file
+        assert_eq!(config.fanart_factor, 7);
+        // command line over environment
+        assert_eq!(config.thumb_factor, 6);
+        assert!(config.dlna);
+    }
+
+    #[test]
+    fn switches_take_an_optional_value() {
+        assert_eq!(args(&["--dlna"]).dlna, Some(true));
+        assert_eq!(args(&["--dlna=false"]).dlna, Some(false));
+        // not the value of the switch
+        assert!(Cli::try_parse_from(["rvs", "--dlna", "false"]).is_err());
+        assert_eq!(args(&["--prefer-unwatched"]).prefer_unwatched, Some(true));
+        assert_eq!(args(&[]).allow_unrated, None);
+    }
+
+    #[test]
+    fn names_unknown_keys() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = write(&dir, "directory = \"/movies\"\ntrailer_facter = 2\n");
+        let error = Config::load(Some(&path), &ConfigArgs::default())
+            .unwrap_err()
+            .to_string();
+        assert!(error.contains("config.toml"), "{}", error);
+        assert!(error.contains("trailer_facter"), "{}", error);
+
+        let path = write(
+            &dir,
+            "[[channels]]\nname = \"kids\"\ngenre = [\"Comedy\"]\n",
+        );
+        let error = Config::load(Some(&path), &ConfigArgs::default())
+            .unwrap_err()
+            .to_string();
+        assert!(error.contains("genre"), "{}", error);
+    }
+
+    #[test]
+    fn a_missing_explicit_file_is_an_error() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("missing.toml");
+        assert!(Config::load(Some(&path), &ConfigArgs::default()).is_err());
+    }
+
+    #[test]
+    fn validation_names_the_key() {
+        let dir = tempfile::tempdir().unwrap();
+        let valid = Config {
+            directory: format!("{}/", dir.path().display()),
+            state_file: dir.path().join("state.json"),
+            ..Default::default()
+        };
+        assert_eq!(valid.validate(), Vec::<String>::new());
+
+        let config = Config {
+            directory: String::new(),
+            poster_factor: -1,
+            favorite_factor: 0,
+            max_certification: Some("kids".to_string()),
+            basic_auth_user: Some("alice".to_string()),
+            tls_key: Some(dir.path().join("key.pem")),
+            http_redirect_port: Some(80),
+            ambient_interval: 0,
+            channels: vec![
+                Channel {
+                    name: "kids".to_string(),
+                    genres: Vec::new(),
+                    max_certification: Some("teens".to_string()),
+                },
+                Channel {
+                    name: "kids".to_string(),
+                    genres: Vec::new(),
+                    max_certification: None,
+                },
+            ],
+            ..valid
+        };
+        let keys: Vec<String> = config
+            .validate()
+            .iter()
+            .map(|e| e.split(':').next().unwrap().to_string())
+            .collect();
+        assert_eq!(
+            keys,
+            [
+                "directory",
+                "poster_factor",
+                "favorite_factor",
+                "max_certification",
+                "basic_auth_user",
+                "tls_cert",
+                "tls_key",
+                "http_redirect_port",
+                "ambient_interval",
+                "channels[0].max_certification",
+                "channels[1].name",
+            ]
+        );
+    }
+}
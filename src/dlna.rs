@@ -0,0 +1,1024 @@
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr, UdpSocket};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use actix_web::http::header::{HeaderName, HeaderValue};
+use actix_web::{web, HttpRequest, HttpResponse};
+use sha2::{Digest, Sha256};
+use socket2::{Domain, Protocol, Socket, Type};
+
+use crate::auth::{Profile, DEFAULT_PROFILE};
+use crate::{base_url, escape_html, media_url, movie_weight, shuffled_tv_movies, Data, Movie};
+
+const SSDP_ADDR: Ipv4Addr = Ipv4Addr::new(239, 255, 255, 250);
+const SSDP_PORT: u16 = 1900;
+/// Devices forget a server that doesn't announce itself within max-age.
+const MAX_AGE: u64 = 1800;
+const NOTIFY_INTERVAL: Duration = Duration::from_secs(MAX_AGE / 3);
+const MEDIA_SERVER: &str = "urn:schemas-upnp-org:device:MediaServer:1";
+const CONTENT_DIRECTORY: &str = "urn:schemas-upnp-org:service:ContentDirectory:1";
+const CONNECTION_MANAGER: &str = "urn:schemas-upnp-org:service:ConnectionManager:1";
+/// Streaming with byte range seeking.
+const CONTENT_FEATURES: &str =
+    "DLNA.ORG_OP=01;DLNA.ORG_CI=0;DLNA.ORG_FLAGS=01700000000000000000000000000000";
+const SERVER: &str = concat!(
+    "Linux/1.0 UPnP/1.0 random_video_server/",
+    env!("CARGO_PKG_VERSION")
+);
+
+/// The UPnP media server, browsing acts as the default profile.
+#[derive(Debug)]
+pub struct Dlna {
+    uuid: String,
+    name: String,
+    /// Order of the "Random" folder per client, kept while it pages through it.
+    random: Mutex<HashMap<Option<IpAddr>, Vec<usize>>>,
+}
+
+impl Dlna {
+    /// The uuid stays the same across restarts so TVs keep their bookmarks.
+    pub fn new(name: &str, directory: &str, port: u16) -> Dlna {
+        let hash = Sha256::digest(format!("{}\n{}\n{}", name, directory, port));
+        let hex: String = hash.iter().map(|b| format!("{:02x}", b)).collect();
+        Dlna {
+            uuid: format!(
+                "{}-{}-{}-{}-{}",
+                &hex[0..8],
+                &hex[8..12],
+                &hex[12..16],
+                &hex[16..20],
+                &hex[20..32]
+            ),
+            name: name.to_string(),
+            random: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn notification_types(&self) -> Vec<String> {
+        vec![
+            "upnp:rootdevice".to_string(),
+            format!("uuid:{}", self.uuid),
+            MEDIA_SERVER.to_string(),
+            CONTENT_DIRECTORY.to_string(),
+            CONNECTION_MANAGER.to_string(),
+        ]
+    }
+
+    fn usn(&self, nt: &str) -> String {
+        if nt.starts_with("uuid:") {
+            nt.to_string()
+        } else {
+            format!("uuid:{}::{}", self.uuid, nt)
+        }
+    }
+
+    /// Replies to an SSDP `M-SEARCH`, none if the request isn't one or asks for
+    /// something else.
+    fn answer_search(&self, request: &str, location: &str) -> Vec<String> {
+        let mut lines = request.lines();
+        if !lines
+            .next()
+            .is_some_and(|l| l.trim().eq_ignore_ascii_case("M-SEARCH * HTTP/1.1"))
+        {
+            return Vec::new();
+        }
+        let header = |name: &str| {
+            request.lines().find_map(|l| {
+                let (key, value) = l.split_once(':')?;
+                key.trim()
+                    .eq_ignore_ascii_case(name)
+                    .then(|| value.trim().trim_matches('"').to_string())
+            })
+        };
+        if header("MAN").as_deref() != Some("ssdp:discover") {
+            return Vec::new();
+        }
+        let st = header("ST").unwrap_or_default();
+        self.notification_types()
+            .into_iter()
+            .filter(|nt| st == "ssdp:all" || *nt == st)
+            .map(|nt| {
+                format!(
+                    "HTTP/1.1 200 OK\r\nCACHE-CONTROL: max-age={}\r\nEXT:\r\nLOCATION: {}\r\nSERVER: {}\r\nST: {}\r\nUSN: {}\r\n\r\n",
+                    MAX_AGE,
+                    location,
+                    SERVER,
+                    nt,
+                    self.usn(&nt)
+                )
+            })
+            .collect()
+    }
+
+    fn alive(&self, location: &str) -> Vec<String> {
+        self.notification_types()
+            .into_iter()
+            .map(|nt| {
+                format!(
+                    "NOTIFY * HTTP/1.1\r\nHOST: {}:{}\r\nCACHE-CONTROL: max-age={}\r\nLOCATION: {}\r\nNT: {}\r\nNTS: ssdp:alive\r\nSERVER: {}\r\nUSN: {}\r\n\r\n",
+                    SSDP_ADDR,
+                    SSDP_PORT,
+                    MAX_AGE,
+                    location,
+                    nt,
+                    SERVER,
+                    self.usn(&nt)
+                )
+            })
+            .collect()
+    }
+}
+
+/// Address of the interface that reaches `peer`, what a device there can connect to.
+fn local_ip_for(peer: SocketAddr) -> Option<IpAddr> {
+    let socket = UdpSocket::bind("0.0.0.0:0").ok()?;
+    socket.connect(peer).ok()?;
+    socket.local_addr().ok().map(|a| a.ip())
+}
+
+fn location(ip_bind: &str, port: u16, peer: SocketAddr) -> Option<String> {
+    let ip = match ip_bind.parse::<IpAddr>() {
+        Ok(ip) if !ip.is_unspecified() => ip,
+        _ => local_ip_for(peer)?,
+    };
+    Some(format!(
+        "http://{}/dlna/description.xml",
+        SocketAddr::new(ip, port)
+    ))
+}
+
+/// Answers discovery requests and announces the server on the local network until
+/// the process ends.
+pub fn start_ssdp(dlna: Arc<Dlna>, ip_bind: String, port: u16) -> io::Result<()> {
+    let socket = Socket::new(Domain::IPV4, Type::DGRAM, Some(Protocol::UDP))?;
+    // other media servers on this machine listen as well
+    socket.set_reuse_address(true)?;
+    socket.bind(&SocketAddr::from((Ipv4Addr::UNSPECIFIED, SSDP_PORT)).into())?;
+    let socket: UdpSocket = socket.into();
+    socket.join_multicast_v4(&SSDP_ADDR, &Ipv4Addr::UNSPECIFIED)?;
+    socket.set_read_timeout(Some(Duration::from_secs(5)))?;
+    let multicast = SocketAddr::from((SSDP_ADDR, SSDP_PORT));
+    tracing::info!(uuid = %dlna.uuid, "announcing dlna media server");
+
+    std::thread::spawn(move || {
+        let mut last_notify: Option<Instant> = None;
+        let mut buf = [0; 2048];
+        loop {
+            if last_notify.is_none_or(|t| t.elapsed() >= NOTIFY_INTERVAL) {
+                if let Some(location) = location(&ip_bind, port, multicast) {
+                    for message in dlna.alive(&location) {
+                        let _ = socket.send_to(message.as_bytes(), multicast);
+                    }
+                }
+                last_notify = Some(Instant::now());
+            }
+            let (n, peer) = match socket.recv_from(&mut buf) {
+                Ok(received) => received,
+                Err(e)
+                    if matches!(
+                        e.kind(),
+                        io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+                    ) =>
+                {
+                    continue
+                }
+                Err(e) => {
+                    tracing::warn!(error = %e, "ssdp stopped");
+                    return;
+                }
+            };
+            let request = String::from_utf8_lossy(&buf[..n]);
+            // most traffic are announcements of other devices
+            if !request.starts_with("M-SEARCH") {
+                continue;
+            }
+            let Some(location) = location(&ip_bind, port, peer) else {
+                continue;
+            };
+            let answers = dlna.answer_search(&request, &location);
+            if !answers.is_empty() {
+                tracing::debug!(%peer, "answering ssdp search");
+            }
+            for answer in answers {
+                let _ = socket.send_to(answer.as_bytes(), peer);
+            }
+        }
+    });
+    Ok(())
+}
+
+fn xml(body: String) -> HttpResponse {
+    HttpResponse::Ok()
+        .content_type("text/xml; charset=\"utf-8\"")
+        .body(body)
+}
+
+fn not_found() -> HttpResponse {
+    HttpResponse::NotFound().body("Not found")
+}
+
+pub async fn description(data: web::Data<Arc<Data>>) -> HttpResponse {
+    let Some(dlna) = data.dlna.as_deref() else {
+        return not_found();
+    };
+    let service = |kind: &str, name: &str| {
+        format!(
+            "<service><serviceType>{}</serviceType><serviceId>urn:upnp-org:serviceId:{name}</serviceId>\
+             <SCPDURL>/dlna/{name}.xml</SCPDURL><controlURL>/dlna/control/{name}</controlURL>\
+             <eventSubURL>/dlna/event/{name}</eventSubURL></service>",
+            kind
+        )
+    };
+    xml(format!(
+        r#"<?xml version="1.0" encoding="utf-8"?>
+<root xmlns="urn:schemas-upnp-org:device-1-0" xmlns:dlna="urn:schemas-dlna-org:device-1-0">
+<specVersion><major>1</major><minor>0</minor></specVersion>
+<device>
+<deviceType>{}</deviceType>
+<friendlyName>{}</friendlyName>
+<manufacturer>random_video_server</manufacturer>
+<modelName>random_video_server</modelName>
+<modelNumber>{}</modelNumber>
+<UDN>uuid:{}</UDN>
+<dlna:X_DLNADOC>DMS-1.50</dlna:X_DLNADOC>
+<serviceList>{}{}</serviceList>
+</device>
+</root>"#,
+        MEDIA_SERVER,
+        escape_html(&dlna.name),
+        env!("CARGO_PKG_VERSION"),
+        dlna.uuid,
+        service(CONTENT_DIRECTORY, "ContentDirectory"),
+        service(CONNECTION_MANAGER, "ConnectionManager"),
+    ))
+}
+
+/// `(name, [(argument, direction, state variable)])`
+type Action = (
+    &'static str,
+    &'static [(&'static str, &'static str, &'static str)],
+);
+
+const CONTENT_DIRECTORY_ACTIONS: [Action; 4] = [
+    (
+        "Browse",
+        &[
+            ("ObjectID", "in", "A_ARG_TYPE_ObjectID"),
+            ("BrowseFlag", "in", "A_ARG_TYPE_BrowseFlag"),
+            ("Filter", "in", "A_ARG_TYPE_Filter"),
+            ("StartingIndex", "in", "A_ARG_TYPE_Index"),
+            ("RequestedCount", "in", "A_ARG_TYPE_Count"),
+            ("SortCriteria", "in", "A_ARG_TYPE_SortCriteria"),
+            ("Result", "out", "A_ARG_TYPE_Result"),
+            ("NumberReturned", "out", "A_ARG_TYPE_Count"),
+            ("TotalMatches", "out", "A_ARG_TYPE_Count"),
+            ("UpdateID", "out", "A_ARG_TYPE_UpdateID"),
+        ],
+    ),
+    (
+        "GetSearchCapabilities",
+        &[("SearchCaps", "out", "SearchCapabilities")],
+    ),
+    (
+        "GetSortCapabilities",
+        &[("SortCaps", "out", "SortCapabilities")],
+    ),
+    ("GetSystemUpdateID", &[("Id", "out", "SystemUpdateID")]),
+];
+
+/// `(name, type, allowed values)`
+type StateVariable = (&'static str, &'static str, &'static [&'static str]);
+
+const CONTENT_DIRECTORY_STATE: [StateVariable; 13] = [
+    ("A_ARG_TYPE_ObjectID", "string", &[]),
+    (
+        "A_ARG_TYPE_BrowseFlag",
+        "string",
+        &["BrowseMetadata", "BrowseDirectChildren"],
+    ),
+    ("A_ARG_TYPE_Filter", "string", &[]),
+    ("A_ARG_TYPE_Index", "ui4", &[]),
+    ("A_ARG_TYPE_Count", "ui4", &[]),
+    ("A_ARG_TYPE_SortCriteria", "string", &[]),
+    ("A_ARG_TYPE_Result", "string", &[]),
+    ("A_ARG_TYPE_UpdateID", "ui4", &[]),
+    ("SearchCapabilities", "string", &[]),
+    ("SortCapabilities", "string", &[]),
+    ("SystemUpdateID", "ui4", &[]),
+    ("ContainerUpdateIDs", "string", &[]),
+    ("TransferIDs", "string", &[]),
+];
+
+const CONNECTION_MANAGER_ACTIONS: [Action; 3] = [
+    (
+        "GetProtocolInfo",
+        &[
+            ("Source", "out", "SourceProtocolInfo"),
+            ("Sink", "out", "SinkProtocolInfo"),
+        ],
+    ),
+    (
+        "GetCurrentConnectionIDs",
+        &[("ConnectionIDs", "out", "CurrentConnectionIDs")],
+    ),
+    (
+        "GetCurrentConnectionInfo",
+        &[
+            ("ConnectionID", "in", "A_ARG_TYPE_ConnectionID"),
+            ("RcsID", "out", "A_ARG_TYPE_RcsID"),
+            ("AVTransportID", "out", "A_ARG_TYPE_AVTransportID"),
+            ("ProtocolInfo", "out", "A_ARG_TYPE_ProtocolInfo"),
+            (
+                "PeerConnectionManager",
+                "out",
+                "A_ARG_TYPE_ConnectionManager",
+            ),
+            ("PeerConnectionID", "out", "A_ARG_TYPE_ConnectionID"),
+            ("Direction", "out", "A_ARG_TYPE_Direction"),
+            ("Status", "out", "A_ARG_TYPE_ConnectionStatus"),
+        ],
+    ),
+];
+
+const CONNECTION_MANAGER_STATE: [StateVariable; 11] = [
+    ("SourceProtocolInfo", "string", &[]),
+    ("SinkProtocolInfo", "string", &[]),
+    ("CurrentConnectionIDs", "string", &[]),
+    (
+        "A_ARG_TYPE_ConnectionStatus",
+        "string",
+        &[
+            "OK",
+            "ContentFormatMismatch",
+            "InsufficientBandwidth",
+            "UnreliableChannel",
+            "Unknown",
+        ],
+    ),
+    ("A_ARG_TYPE_ConnectionManager", "string", &[]),
+    ("A_ARG_TYPE_Direction", "string", &["Input", "Output"]),
+    ("A_ARG_TYPE_ProtocolInfo", "string", &[]),
+    ("A_ARG_TYPE_ConnectionID", "i4", &[]),
+    ("A_ARG_TYPE_AVTransportID", "i4", &[]),
+    ("A_ARG_TYPE_RcsID", "i4", &[]),
+    ("A_ARG_TYPE_UpdateID", "ui4", &[]),
+];
+
+/// Service description of `/dlna/{service}.xml`.
+pub async fn service_description(
+    data: web::Data<Arc<Data>>,
+    service: web::Path<String>,
+) -> HttpResponse {
+    if data.dlna.is_none() {
+        return not_found();
+    }
+    let (actions, state): (&[Action], &[StateVariable]) = match service.as_str() {
+        "ContentDirectory" => (&CONTENT_DIRECTORY_ACTIONS, &CONTENT_DIRECTORY_STATE),
+        "ConnectionManager" => (&CONNECTION_MANAGER_ACTIONS, &CONNECTION_MANAGER_STATE),
+        _ => return not_found(),
+    };
+    let mut out = String::from(
+        "<?xml version=\"1.0\" encoding=\"utf-8\"?>\n\
+         <scpd xmlns=\"urn:schemas-upnp-org:service-1-0\">\
+         <specVersion><major>1</major><minor>0</minor></specVersion><actionList>",
+    );
+    for (name, arguments) in actions {
+        let _ = write!(out, "<action><name>{}</name><argumentList>", name);
+        for (argument, direction, variable) in arguments.iter() {
+            let _ = write!(
+                out,
+                "<argument><name>{}</name><direction>{}</direction>\
+                 <relatedStateVariable>{}</relatedStateVariable></argument>",
+                argument, direction, variable
+            );
+        }
+        out.push_str("</argumentList></action>");
+    }
+    out.push_str("</actionList><serviceStateTable>");
+    for (name, kind, allowed) in state {
+        let events = if *name == "SystemUpdateID" {
+            "yes"
+        } else {
+            "no"
+        };
+        let _ = write!(
+            out,
+            "<stateVariable sendEvents=\"{}\"><name>{}</name><dataType>{}</dataType>",
+            events, name, kind
+        );
+        if !allowed.is_empty() {
+            out.push_str("<allowedValueList>");
+            for value in allowed.iter() {
+                let _ = write!(out, "<allowedValue>{}</allowedValue>", value);
+            }
+            out.push_str("</allowedValueList>");
+        }
+        out.push_str("</stateVariable>");
+    }
+    out.push_str("</serviceStateTable></scpd>");
+    xml(out)
+}
+
+/// Accepts event subscriptions without ever sending events, some TVs refuse servers
+/// that reject them.
+pub async fn event(req: HttpRequest, data: web::Data<Arc<Data>>) -> HttpResponse {
+    let Some(dlna) = data.dlna.as_deref() else {
+        return not_found();
+    };
+    match req.method().as_str() {
+        "SUBSCRIBE" => HttpResponse::Ok()
+            .insert_header(("SID", format!("uuid:{}", dlna.uuid)))
+            .insert_header(("TIMEOUT", format!("Second-{}", MAX_AGE)))
+            .finish(),
+        "UNSUBSCRIBE" => HttpResponse::Ok().finish(),
+        _ => HttpResponse::MethodNotAllowed().finish(),
+    }
+}
+
+fn soap_response(service: &str, action: &str, arguments: &[(&str, String)]) -> HttpResponse {
+    let mut body = String::new();
+    for (name, value) in arguments {
+        let _ = write!(body, "<{0}>{1}</{0}>", name, escape_html(value));
+    }
+    xml(format!(
+        r#"<?xml version="1.0" encoding="utf-8"?>
+<s:Envelope xmlns:s="http://schemas.xmlsoap.org/soap/envelope/" s:encodingStyle="http://schemas.xmlsoap.org/soap/encoding/"><s:Body><u:{1}Response xmlns:u="{0}">{2}</u:{1}Response></s:Body></s:Envelope>"#,
+        service, action, body
+    ))
+}
+
+fn soap_fault(code: u16, description: &str) -> HttpResponse {
+    HttpResponse::InternalServerError()
+        .content_type("text/xml; charset=\"utf-8\"")
+        .body(format!(
+            r#"<?xml version="1.0" encoding="utf-8"?>
+<s:Envelope xmlns:s="http://schemas.xmlsoap.org/soap/envelope/" s:encodingStyle="http://schemas.xmlsoap.org/soap/encoding/"><s:Body><s:Fault><faultcode>s:Client</faultcode><faultstring>UPnPError</faultstring><detail><UPnPError xmlns="urn:schemas-upnp-org:control-1-0"><errorCode>{}</errorCode><errorDescription>{}</errorDescription></UPnPError></detail></s:Fault></s:Body></s:Envelope>"#,
+            code, description
+        ))
+}
+
+/// SOAP actions posted to `/dlna/control/{service}`.
+pub async fn control(
+    req: HttpRequest,
+    data: web::Data<Arc<Data>>,
+    service: web::Path<String>,
+    body: String,
+) -> HttpResponse {
+    let Some(dlna) = data.dlna.as_deref() else {
+        return not_found();
+    };
+    let service = match service.as_str() {
+        "ContentDirectory" => CONTENT_DIRECTORY,
+        "ConnectionManager" => CONNECTION_MANAGER,
+        _ => return not_found(),
+    };
+    // SOAPACTION: "urn:schemas-upnp-org:service:ContentDirectory:1#Browse"
+    let action = req
+        .headers()
+        .get("SOAPACTION")
+        .and_then(|a| a.to_str().ok())
+        .and_then(|a| a.trim_matches('"').rsplit_once('#'))
+        .map(|(_, action)| action.to_string())
+        .unwrap_or_default();
+    let doc = match roxmltree::Document::parse(&body) {
+        Ok(doc) => doc,
+        Err(_) => return soap_fault(402, "Invalid Args"),
+    };
+    let argument = |name: &str| {
+        doc.descendants()
+            .find(|n| n.tag_name().name() == name)
+            .and_then(|n| n.text())
+            .unwrap_or_default()
+            .trim()
+            .to_string()
+    };
+
+    match (service, action.as_str()) {
+        (CONTENT_DIRECTORY, "Browse") => {
+            let start = argument("StartingIndex").parse().unwrap_or(0);
+            let count = argument("RequestedCount").parse().unwrap_or(0);
+            let profile = Profile(DEFAULT_PROFILE.to_string());
+            let browse = Browse {
+                req: &req,
+                data: &data,
+                dlna,
+                profile: &profile,
+            };
+            match browse.run(&argument("ObjectID"), &argument("BrowseFlag"), start, count) {
+                Ok((result, returned, total)) => soap_response(
+                    service,
+                    "Browse",
+                    &[
+                        ("Result", result),
+                        ("NumberReturned", returned.to_string()),
+                        ("TotalMatches", total.to_string()),
+                        ("UpdateID", "1".to_string()),
+                    ],
+                ),
+                Err((code, description)) => soap_fault(code, description),
+            }
+        }
+        (CONTENT_DIRECTORY, "GetSearchCapabilities") => {
+            soap_response(service, &action, &[("SearchCaps", String::new())])
+        }
+        (CONTENT_DIRECTORY, "GetSortCapabilities") => {
+            soap_response(service, &action, &[("SortCaps", String::new())])
+        }
+        (CONTENT_DIRECTORY, "GetSystemUpdateID") => {
+            soap_response(service, &action, &[("Id", "1".to_string())])
+        }
+        (CONNECTION_MANAGER, "GetProtocolInfo") => soap_response(
+            service,
+            &action,
+            &[
+                (
+                    "Source",
+                    "http-get:*:video/mp4:*,http-get:*:video/webm:*".to_string(),
+                ),
+                ("Sink", String::new()),
+            ],
+        ),
+        (CONNECTION_MANAGER, "GetCurrentConnectionIDs") => {
+            soap_response(service, &action, &[("ConnectionIDs", "0".to_string())])
+        }
+        (CONNECTION_MANAGER, "GetCurrentConnectionInfo") => soap_response(
+            service,
+            &action,
+            &[
+                ("RcsID", "-1".to_string()),
+                ("AVTransportID", "-1".to_string()),
+                ("ProtocolInfo", String::new()),
+                ("PeerConnectionManager", String::new()),
+                ("PeerConnectionID", "-1".to_string()),
+                ("Direction", "Output".to_string()),
+                ("Status", "OK".to_string()),
+            ],
+        ),
+        _ => soap_fault(401, "Invalid Action"),
+    }
+}
+
+const ROOT: &str = "0";
+const ALL: &str = "all";
+const RANDOM: &str = "random";
+/// Clients whose "Random" order is kept.
+const MAX_CLIENTS: usize = 32;
+/// Movie ids are this prefix and the path of the video.
+const MOVIE_PREFIX: &str = "movie:";
+
+struct Browse<'a> {
+    req: &'a HttpRequest,
+    data: &'a Data,
+    dlna: &'a Dlna,
+    profile: &'a Profile,
+}
+
+impl Browse<'_> {
+    /// `(DIDL-Lite, returned, total)` or a UPnP error.
+    fn run(
+        &self,
+        id: &str,
+        flag: &str,
+        start: usize,
+        count: usize,
+    ) -> Result<(String, usize, usize), (u16, &'static str)> {
+        let mut didl = String::from(
+            r#"<DIDL-Lite xmlns="urn:schemas-upnp-org:metadata-1-0/DIDL-Lite/" xmlns:dc="http://purl.org/dc/elements/1.1/" xmlns:upnp="urn:schemas-upnp-org:metadata-1-0/upnp/" xmlns:dlna="urn:schemas-dlna-org:metadata-1-0/">"#,
+        );
+        let (returned, total) = match flag {
+            "BrowseMetadata" => {
+                match id {
+                    ROOT => self.container(&mut didl, ROOT, "-1", &self.dlna.name, 2),
+                    ALL | RANDOM => {
+                        let n = self.all().len();
+                        self.container(&mut didl, id, ROOT, title(id), n)
+                    }
+                    _ => {
+                        let i = self.movie(id).ok_or((701, "No such object"))?;
+                        self.item(&mut didl, ALL, &self.data.movies[i]);
+                    }
+                }
+                (1, 1)
+            }
+            "BrowseDirectChildren" => {
+                let page = |total: usize| {
+                    let end = if count == 0 {
+                        total
+                    } else {
+                        total.min(start.saturating_add(count))
+                    };
+                    start.min(end)..end
+                };
+                match id {
+                    ROOT => {
+                        let n = self.all().len();
+                        let range = page(2);
+                        for container in &[ALL, RANDOM][range.clone()] {
+                            self.container(&mut didl, container, ROOT, title(container), n);
+                        }
+                        (range.len(), 2)
+                    }
+                    ALL | RANDOM => {
+                        let movies = if id == ALL {
+                            self.all()
+                        } else {
+                            self.random(start)
+                        };
+                        let range = page(movies.len());
+                        for &i in &movies[range.clone()] {
+                            self.item(&mut didl, id, &self.data.movies[i]);
+                        }
+                        (range.len(), movies.len())
+                    }
+                    _ if self.movie(id).is_some() => (0, 0),
+                    _ => return Err((701, "No such object")),
+                }
+            }
+            _ => return Err((402, "Invalid Args")),
+        };
+        didl.push_str("</DIDL-Lite>");
+        Ok((didl, returned, total))
+    }
+
+    fn index(&self, m: &Movie) -> Option<usize> {
+        self.data.files.get(&m.movie).map(|(_, i)| i)
+    }
+
+    /// Every movie the profile may see, by title.
+    fn all(&self) -> Vec<usize> {
+        let mut movies: Vec<&Movie> = self
+            .data
+            .movies
+            .iter()
+            .filter(|m| movie_weight(self.data, self.profile, None, m) > 0.0)
+            .collect();
+        movies.sort_by_key(|m| m.title().to_lowercase());
+        movies.iter().filter_map(|m| self.index(m)).collect()
+    }
+
+    /// Shuffled like `/tv` on the first page, later pages continue that order.
+    fn random(&self, start: usize) -> Vec<usize> {
+        let client = self.req.peer_addr().map(|a| a.ip());
+        let mut random = self.dlna.random.lock().unwrap();
+        if start == 0 || !random.contains_key(&client) {
+            // a household has a few TVs, forget them all rather than grow forever
+            if random.len() >= MAX_CLIENTS {
+                random.clear();
+            }
+            let order = shuffled_tv_movies(self.data, self.profile, None)
+                .into_iter()
+                .filter_map(|m| self.index(m))
+                .collect();
+            random.insert(client, order);
+        }
+        random[&client].clone()
+    }
+
+    fn movie(&self, id: &str) -> Option<usize> {
+        let path = id.strip_prefix(MOVIE_PREFIX)?;
+        let i = self.data.files.get(Path::new(path))?.1;
+        let m = &self.data.movies[i];
+        (m.movie == Path::new(path) && movie_weight(self.data, self.profile, None, m) > 0.0)
+            .then_some(i)
+    }
+
+    fn container(&self, didl: &mut String, id: &str, parent: &str, title: &str, children: usize) {
+        let _ = write!(
+            didl,
+            r#"<container id="{}" parentID="{}" restricted="1" searchable="0" childCount="{}"><dc:title>{}</dc:title><upnp:class>object.container.storageFolder</upnp:class></container>"#,
+            escape_html(id),
+            escape_html(parent),
+            children,
+            escape_html(title)
+        );
+    }
+
+    fn item(&self, didl: &mut String, parent: &str, m: &Movie) {
+        let base = base_url(self.req);
+        let id = format!("{}{}", MOVIE_PREFIX, m.movie.display());
+        let _ = write!(
+            didl,
+            r#"<item id="{}" parentID="{}" restricted="1"><dc:title>{}</dc:title><upnp:class>object.item.videoItem.movie</upnp:class>"#,
+            escape_html(&id),
+            escape_html(parent),
+            escape_html(&m.title())
+        );
+        if let Some(year) = &m.info.year {
+            let _ = write!(didl, "<dc:date>{}-01-01</dc:date>", escape_html(year));
+        }
+        for genre in &m.info.genres {
+            let _ = write!(didl, "<upnp:genre>{}</upnp:genre>", escape_html(genre));
+        }
+        for director in &m.info.directors {
+            let _ = write!(
+                didl,
+                "<upnp:director>{}</upnp:director>",
+                escape_html(director)
+            );
+        }
+        if let Some(plot) = &m.info.plot {
+            let _ = write!(
+                didl,
+                "<dc:description>{}</dc:description>",
+                escape_html(plot)
+            );
+        }
+        if let Some(image) = m.poster.iter().chain(&m.thumb).chain(&m.fanarts).next() {
+            let _ = write!(
+                didl,
+                r#"<upnp:albumArtURI dlna:profileID="JPEG_TN">{}</upnp:albumArtURI>"#,
+                escape_html(&(base.clone() + &media_url(self.data, self.profile, "image", image)))
+            );
+        }
+        let mime = match m.movie.extension().and_then(|e| e.to_str()) {
+            Some("webm") => "video/webm",
+            _ => "video/mp4",
+        };
+        let mut res = format!(
+            r#"<res protocolInfo="http-get:*:{}:{}""#,
+            mime, CONTENT_FEATURES
+        );
+        if let Some(size) = m.size {
+            let _ = write!(res, r#" size="{}""#, size);
+        }
+        if let Some(minutes) = m
+            .info
+            .runtime
+            .as_ref()
+            .and_then(|r| r.trim().parse::<u64>().ok())
+        {
+            let _ = write!(
+                res,
+                r#" duration="{}:{:02}:00.000""#,
+                minutes / 60,
+                minutes % 60
+            );
+        }
+        let _ = write!(
+            didl,
+            "{}>{}</res></item>",
+            res,
+            escape_html(&(base + &media_url(self.data, self.profile, "movie", &m.movie)))
+        );
+    }
+}
+
+fn title(id: &str) -> &'static str {
+    match id {
+        RANDOM => "Random",
+        _ => "All movies",
+    }
+}
+
+/// `192.168.1.0/24`, `fd00::/8` or a single address.
+pub fn parse_network(network: &str) -> Option<(IpAddr, u8)> {
+    let (ip, bits) = match network.split_once('/') {
+        Some((ip, bits)) => (ip.parse::<IpAddr>().ok()?, Some(bits.parse::<u8>().ok()?)),
+        None => (network.parse().ok()?, None),
+    };
+    let max = if ip.is_ipv4() { 32 } else { 128 };
+    let bits = bits.unwrap_or(max);
+    (bits <= max).then_some((ip, bits))
+}
+
+/// Whether `ip` is in one of the `networks`, invalid ones match nothing.
+pub fn in_networks(ip: IpAddr, networks: &[String]) -> bool {
+    networks
+        .iter()
+        .filter_map(|n| parse_network(n))
+        .any(|(network, bits)| match (ip.to_canonical(), network) {
+            (IpAddr::V4(ip), IpAddr::V4(network)) => {
+                let mask = u32::MAX.checked_shl(32 - bits as u32).unwrap_or(0);
+                u32::from(ip) & mask == u32::from(network) & mask
+            }
+            (IpAddr::V6(ip), IpAddr::V6(network)) => {
+                let mask = u128::MAX.checked_shl(128 - bits as u32).unwrap_or(0);
+                u128::from(ip) & mask == u128::from(network) & mask
+            }
+            _ => false,
+        })
+}
+
+/// DLNA clients ask `/movie` for these headers before streaming.
+pub fn add_stream_headers(req: &HttpRequest, res: &mut HttpResponse) {
+    if req.headers().contains_key("getcontentFeatures.dlna.org") {
+        res.headers_mut().insert(
+            HeaderName::from_static("contentfeatures.dlna.org"),
+            HeaderValue::from_static(CONTENT_FEATURES),
+        );
+        res.headers_mut().insert(
+            HeaderName::from_static("transfermode.dlna.org"),
+            HeaderValue::from_static("Streaming"),
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn matches_networks() {
+        let networks = [
+            "192.168.1.0/24".to_string(),
+            "10.0.0.5".to_string(),
+            "fd00::/8".to_string(),
+            "not a network".to_string(),
+        ];
+        let allowed = |ip: &str| in_networks(ip.parse().unwrap(), &networks);
+        assert!(allowed("192.168.1.20"));
+        assert!(allowed("::ffff:192.168.1.20"));
+        assert!(allowed("10.0.0.5"));
+        assert!(allowed("fd12::1"));
+        assert!(!allowed("192.168.2.20"));
+        assert!(!allowed("10.0.0.6"));
+        assert!(!allowed("127.0.0.1"));
+        assert!(!allowed("2001:db8::1"));
+        assert!(!in_networks("192.168.1.20".parse().unwrap(), &[]));
+        assert!(in_networks(
+            "8.8.8.8".parse().unwrap(),
+            &["0.0.0.0/0".to_string()]
+        ));
+
+        assert_eq!(parse_network("10.0.0.0/33"), None);
+        assert_eq!(parse_network("10.0.0.0/"), None);
+        assert_eq!(parse_network("::1"), Some(("::1".parse().unwrap(), 128)));
+    }
+
+    #[test]
+    fn answers_matching_searches() {
+        let dlna = Dlna::new("Movies", "/movies/", 3070);
+        let location = "http://192.168.1.2:3070/dlna/description.xml";
+        let search = |st: &str| {
+            format!(
+                "M-SEARCH * HTTP/1.1\r\nHOST: 239.255.255.250:1900\r\nMAN: \"ssdp:discover\"\r\nMX: 2\r\nST: {}\r\n\r\n",
+                st
+            )
+        };
+
+        let answers = dlna.answer_search(&search(MEDIA_SERVER), location);
+        assert_eq!(answers.len(), 1);
+        assert!(answers[0].starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(answers[0].contains(&format!("LOCATION: {}\r\n", location)));
+        assert!(answers[0].contains(&format!("USN: uuid:{}::{}\r\n", dlna.uuid, MEDIA_SERVER)));
+
+        assert_eq!(dlna.answer_search(&search("ssdp:all"), location).len(), 5);
+        assert!(dlna
+            .answer_search(
+                &search("urn:schemas-upnp-org:device:MediaRenderer:1"),
+                location
+            )
+            .is_empty());
+        assert!(dlna
+            .answer_search("NOTIFY * HTTP/1.1\r\nNT: upnp:rootdevice\r\n\r\n", location)
+            .is_empty());
+    }
+
+    #[test]
+    fn keeps_the_uuid() {
+        let a = Dlna::new("Movies", "/movies/", 3070);
+        assert_eq!(a.uuid, Dlna::new("Movies", "/movies/", 3070).uuid);
+        assert_ne!(a.uuid, Dlna::new("Movies", "/other/", 3070).uuid);
+        assert_eq!(a.uuid.len(), 36);
+    }
+
+    fn browse(
+        data: &Data,
+        peer: &str,
+        id: &str,
+        flag: &str,
+        start: usize,
+        count: usize,
+    ) -> Result<(String, usize, usize), (u16, &'static str)> {
+        let req = actix_web::test::TestRequest::default()
+            .peer_addr(peer.parse().unwrap())
+            .to_http_request();
+        let profile = Profile(DEFAULT_PROFILE.to_string());
+        let browse = Browse {
+            req: &req,
+            data,
+            dlna: data.dlna.as_deref().unwrap(),
+            profile: &profile,
+        };
+        browse.run(id, flag, start, count)
+    }
+
+    /// Titles of the items in a DIDL-Lite result, in order.
+    fn titles(didl: &str) -> Vec<String> {
+        let doc = roxmltree::Document::parse(didl).unwrap();
+        doc.descendants()
+            .filter(|n| n.tag_name().name() == "item")
+            .filter_map(|n| n.descendants().find(|c| c.tag_name().name() == "title"))
+            .filter_map(|n| n.text())
+            .map(|t| t.to_string())
+            .collect()
+    }
+
+    fn test_data(titles: &[&str]) -> Data {
+        crate::tests::test_data(
+            titles.iter().map(|t| crate::tests::test_movie(t)).collect(),
+            Default::default(),
+        )
+    }
+
+    const PEER: &str = "192.168.1.20:50000";
+
+    #[test]
+    fn browses_the_root() {
+        let data = test_data(&["A", "B"]);
+        let (didl, returned, total) =
+            browse(&data, PEER, ROOT, "BrowseDirectChildren", 0, 0).unwrap();
+        assert_eq!((returned, total), (2, 2));
+        assert!(didl.contains(r#"<container id="all" parentID="0""#));
+        assert!(didl.contains(r#"<container id="random" parentID="0""#));
+
+        let (didl, returned, total) =
+            browse(&data, PEER, ROOT, "BrowseDirectChildren", 1, 5).unwrap();
+        assert_eq!((returned, total), (1, 2));
+        assert!(!didl.contains(r#"id="all""#));
+        assert!(didl.contains(r#"id="random""#));
+
+        let (didl, returned, total) = browse(&data, PEER, ROOT, "BrowseMetadata", 0, 0).unwrap();
+        assert_eq!((returned, total), (1, 1));
+        assert!(didl.contains(r#"<container id="0" parentID="-1""#));
+    }
+
+    #[test]
+    fn pages_through_all_movies_by_title() {
+        let data = test_data(&["c", "A", "b"]);
+        let (didl, returned, total) =
+            browse(&data, PEER, ALL, "BrowseDirectChildren", 0, 0).unwrap();
+        assert_eq!((returned, total), (3, 3));
+        assert_eq!(titles(&didl), ["A", "b", "c"]);
+
+        let (didl, returned, total) =
+            browse(&data, PEER, ALL, "BrowseDirectChildren", 1, 1).unwrap();
+        assert_eq!((returned, total), (1, 3));
+        assert_eq!(titles(&didl), ["b"]);
+
+        let (didl, returned, total) =
+            browse(&data, PEER, ALL, "BrowseDirectChildren", 5, 2).unwrap();
+        assert_eq!((returned, total), (0, 3));
+        assert!(titles(&didl).is_empty());
+    }
+
+    #[test]
+    fn pages_continue_the_random_order_of_each_client() {
+        let names: Vec<String> = (0..20).map(|i| format!("Movie {:02}", i)).collect();
+        let names: Vec<&str> = names.iter().map(|n| n.as_str()).collect();
+        let data = test_data(&names);
+        let other = "192.168.1.21:50000";
+
+        let (first, returned, total) =
+            browse(&data, PEER, RANDOM, "BrowseDirectChildren", 0, 10).unwrap();
+        assert_eq!((returned, total), (10, 20));
+        // another TV opening the folder doesn't reshuffle the first one's pages
+        browse(&data, other, RANDOM, "BrowseDirectChildren", 0, 10).unwrap();
+        let (second, returned, _) =
+            browse(&data, PEER, RANDOM, "BrowseDirectChildren", 10, 10).unwrap();
+        assert_eq!(returned, 10);
+
+        let mut seen = titles(&first);
+        seen.extend(titles(&second));
+        seen.sort();
+        assert_eq!(seen, names);
+    }
+
+    #[test]
+    fn browses_movies() {
+        let data = test_data(&["A"]);
+        let (didl, returned, total) =
+            browse(&data, PEER, "movie:A/A.mp4", "BrowseMetadata", 0, 0).unwrap();
+        assert_eq!((returned, total), (1, 1));
+        assert_eq!(titles(&didl), ["A"]);
+        assert!(didl.contains("/movie/A/A.mp4</res>"));
+
+        let (_, returned, total) =
+            browse(&data, PEER, "movie:A/A.mp4", "BrowseDirectChildren", 0, 0).unwrap();
+        assert_eq!((returned, total), (0, 0));
+    }
+
+    #[test]
+    fn unknown_objects_are_errors() {
+        let data = test_data(&["A"]);
+        for flag in ["BrowseMetadata", "BrowseDirectChildren"] {
+            assert_eq!(
+                browse(&data, PEER, "movie:B/B.mp4", flag, 0, 0)
+                    .unwrap_err()
+                    .0,
+                701
+            );
+            assert_eq!(browse(&data, PEER, "other", flag, 0, 0).unwrap_err().0, 701);
+        }
+        assert_eq!(
+            browse(&data, PEER, ROOT, "BrowseEverything", 0, 0)
+                .unwrap_err()
+                .0,
+            402
+        );
+    }
+}
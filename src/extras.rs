@@ -0,0 +1,214 @@
+use std::fs::read_dir;
+use std::path::{Path, PathBuf};
+
+use crate::striped;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExtraKind {
+    Trailer,
+    BehindTheScenes,
+    Featurette,
+    DeletedScene,
+    Interview,
+    Scene,
+    Short,
+    Other,
+}
+
+impl ExtraKind {
+    /// Kind of the standard Kodi/Jellyfin extras folder `name`.
+    pub fn from_folder(name: &str) -> Option<ExtraKind> {
+        match name.to_lowercase().as_str() {
+            "trailers" => Some(ExtraKind::Trailer),
+            "behind the scenes" => Some(ExtraKind::BehindTheScenes),
+            "featurettes" => Some(ExtraKind::Featurette),
+            "deleted scenes" => Some(ExtraKind::DeletedScene),
+            "interviews" => Some(ExtraKind::Interview),
+            "scenes" => Some(ExtraKind::Scene),
+            "shorts" => Some(ExtraKind::Short),
+            "extras" | "other" => Some(ExtraKind::Other),
+            _ => None,
+        }
+    }
+
+    /// Kind of the video `<name>-<suffix>` next to the movie `name`, e.g.
+    /// `Movie (2000)-featurette.mp4`. Trailers are found by the scanner itself.
+    pub fn from_suffix(name: &str, stem: &str) -> Option<ExtraKind> {
+        let suffix = stem.strip_prefix(name)?.strip_prefix('-')?;
+        match suffix.to_lowercase().as_str() {
+            "behindthescenes" => Some(ExtraKind::BehindTheScenes),
+            "featurette" => Some(ExtraKind::Featurette),
+            "deleted" | "deletedscene" => Some(ExtraKind::DeletedScene),
+            "interview" => Some(ExtraKind::Interview),
+            "scene" => Some(ExtraKind::Scene),
+            "short" => Some(ExtraKind::Short),
+            "extra" | "other" => Some(ExtraKind::Other),
+            _ => None,
+        }
+    }
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            ExtraKind::Trailer => "Trailer",
+            ExtraKind::BehindTheScenes => "Behind the scenes",
+            ExtraKind::Featurette => "Featurette",
+            ExtraKind::DeletedScene => "Deleted scene",
+            ExtraKind::Interview => "Interview",
+            ExtraKind::Scene => "Scene",
+            ExtraKind::Short => "Short",
+            ExtraKind::Other => "Extra",
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Extra {
+    pub kind: ExtraKind,
+    pub path: PathBuf,
+}
+
+/// Whether `path` lies in an extras folder, those videos are never movies themselves.
+pub fn in_extras_folder(path: &Path) -> bool {
+    path.parent().is_some_and(|parent| {
+        parent
+            .components()
+            .any(|c| ExtraKind::from_folder(&c.as_os_str().to_string_lossy()).is_some())
+    })
+}
+
+fn videos_in(folder: &Path) -> Vec<PathBuf> {
+    let Ok(entries) = read_dir(folder) else {
+        return Vec::new();
+    };
+    let mut videos: Vec<PathBuf> = entries
+        .flatten()
+        .map(|e| e.path())
+        .filter(|p| {
+            p.is_file()
+                && p.extension()
+                    .map(|e| e.to_string_lossy().to_lowercase())
+                    .is_some_and(|e| e == "mp4" || e == "webm")
+        })
+        .collect();
+    videos.sort();
+    videos
+}
+
+/// Finds the videos in the extras folders next to the movie, e.g. `featurettes/Making of.mp4`,
+/// and those named with an extras suffix, e.g. `Movie-featurette.mp4`.
+pub fn find_extras(root_dir: &String, video: &Path) -> Vec<Extra> {
+    let mut ret = Vec::new();
+    let Some(parent) = video.parent() else {
+        return ret;
+    };
+    let Ok(folders) = read_dir(parent) else {
+        return ret;
+    };
+    let mut folders: Vec<(ExtraKind, PathBuf)> = folders
+        .flatten()
+        .filter(|e| e.path().is_dir())
+        .filter_map(|e| {
+            ExtraKind::from_folder(&e.file_name().to_string_lossy()).map(|kind| (kind, e.path()))
+        })
+        .collect();
+    folders.sort_by(|a, b| a.1.cmp(&b.1));
+    for (kind, folder) in folders {
+        for path in videos_in(&folder) {
+            if let Some(path) = striped(root_dir, path) {
+                ret.push(Extra { kind, path });
+            }
+        }
+    }
+
+    let name = video
+        .file_stem()
+        .map(|s| s.to_string_lossy().to_string())
+        .unwrap_or_default();
+    for path in videos_in(parent) {
+        let stem = path
+            .file_stem()
+            .map(|s| s.to_string_lossy().to_string())
+            .unwrap_or_default();
+        if let Some(kind) = ExtraKind::from_suffix(&name, &stem) {
+            if let Some(path) = striped(root_dir, path) {
+                ret.push(Extra { kind, path });
+            }
+        }
+    }
+    ret
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn kinds_from_folder_and_suffix_names() {
+        assert_eq!(
+            ExtraKind::from_folder("Behind The Scenes"),
+            Some(ExtraKind::BehindTheScenes)
+        );
+        assert_eq!(ExtraKind::from_folder("extras"), Some(ExtraKind::Other));
+        assert_eq!(ExtraKind::from_folder("Subs"), None);
+
+        let suffix = |stem| ExtraKind::from_suffix("Movie (2000)", stem);
+        assert_eq!(
+            suffix("Movie (2000)-featurette"),
+            Some(ExtraKind::Featurette)
+        );
+        assert_eq!(
+            suffix("Movie (2000)-Deleted"),
+            Some(ExtraKind::DeletedScene)
+        );
+        assert_eq!(suffix("Movie (2000)-other"), Some(ExtraKind::Other));
+        assert_eq!(suffix("Movie (2000)-cd1"), None);
+        assert_eq!(suffix("Movie (2000)"), None);
+        assert_eq!(suffix("Other-featurette"), None);
+
+        assert!(in_extras_folder(Path::new(
+            "Movie/featurettes/Making of.mp4"
+        )));
+        assert!(!in_extras_folder(Path::new("Movie/Movie.mp4")));
+    }
+
+    #[test]
+    fn finds_extras_next_to_the_movie() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = format!("{}/", dir.path().display());
+        for file in [
+            "Movie/Movie.mp4",
+            "Movie/Movie-short.webm",
+            "Movie/Movie-cd1.mp4",
+            "Movie/Deleted Scenes/Cut.mp4",
+            "Movie/featurettes/Making of.mp4",
+            "Movie/featurettes/notes.txt",
+            "Movie/trailers/Teaser.mp4",
+            "Movie/unknown/Other.mp4",
+        ] {
+            let path = dir.path().join(file);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, "").unwrap();
+        }
+        let extras: Vec<(ExtraKind, String)> =
+            find_extras(&root, &dir.path().join("Movie/Movie.mp4"))
+                .into_iter()
+                .map(|e| (e.kind, e.path.display().to_string()))
+                .collect();
+        assert_eq!(
+            extras,
+            [
+                (
+                    ExtraKind::DeletedScene,
+                    "Movie/Deleted Scenes/Cut.mp4".to_string()
+                ),
+                (
+                    ExtraKind::Featurette,
+                    "Movie/featurettes/Making of.mp4".to_string()
+                ),
+                (ExtraKind::Trailer, "Movie/trailers/Teaser.mp4".to_string()),
+                (ExtraKind::Short, "Movie/Movie-short.webm".to_string()),
+            ]
+        );
+    }
+}
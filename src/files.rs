@@ -0,0 +1,236 @@
+use std::collections::HashMap;
+use std::path::{Component, Path, PathBuf};
+
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+
+use crate::Movie;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FileKind {
+    Video,
+    Image,
+    Subtitle,
+}
+
+/// What to do with symlinks below the library directory.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum SymlinkPolicy {
+    /// Never serve a file reached through a symlink.
+    Deny,
+    /// Follow symlinks as long as the target stays inside the library directory.
+    #[default]
+    WithinRoot,
+    /// Follow symlinks anywhere.
+    Follow,
+}
+
+/// All files found by the scanner, the only ones the file routes serve.
+#[derive(Debug, Clone, Default)]
+pub struct FileIndex {
+    files: HashMap<PathBuf, (FileKind, usize)>,
+}
+
+impl FileIndex {
+    pub fn new(movies: &[Movie]) -> FileIndex {
+        let mut files = HashMap::new();
+        for (i, m) in movies.iter().enumerate() {
+            for p in std::iter::once(&m.movie)
+                .chain(&m.parts)
+                .chain(m.versions.iter().flat_map(|v| v.files()))
+                .chain(&m.trailer)
+            {
+                files.insert(p.clone(), (FileKind::Video, i));
+            }
+            for p in m
+                .poster
+                .iter()
+                .chain(&m.thumb)
+                .chain(&m.fanarts)
+                .chain(&m.clearlogo)
+                .chain(&m.clearart)
+                .chain(&m.banner)
+                .chain(&m.landscape)
+                .chain(&m.disc)
+            {
+                files.insert(p.clone(), (FileKind::Image, i));
+            }
+            for e in &m.extras {
+                files.insert(e.path.clone(), (FileKind::Video, i));
+            }
+            for s in &m.subtitles {
+                files.insert(s.path.clone(), (FileKind::Subtitle, i));
+            }
+        }
+        FileIndex { files }
+    }
+
+    /// Kind of the file and index of its movie.
+    pub fn get(&self, path: &Path) -> Option<(FileKind, usize)> {
+        self.files.get(path).copied()
+    }
+}
+
+/// Absolute path of `path` below `root`, `None` for anything that must not be served.
+pub fn resolve(root: &Path, path: &str, symlinks: SymlinkPolicy) -> Option<PathBuf> {
+    let relative = Path::new(path);
+    if relative.as_os_str().is_empty()
+        || !relative
+            .components()
+            .all(|c| matches!(c, Component::Normal(_)))
+    {
+        return None;
+    }
+    let full = root.join(relative);
+
+    let resolved = match symlinks {
+        SymlinkPolicy::Deny => {
+            let mut current = root.to_path_buf();
+            for component in relative.components() {
+                current.push(component);
+                if current.symlink_metadata().ok()?.file_type().is_symlink() {
+                    return None;
+                }
+            }
+            full
+        }
+        SymlinkPolicy::WithinRoot => {
+            let canonical = full.canonicalize().ok()?;
+            if !canonical.starts_with(root.canonicalize().ok()?) {
+                return None;
+            }
+            canonical
+        }
+        SymlinkPolicy::Follow => full.canonicalize().ok()?,
+    };
+    if resolved.is_file() {
+        Some(resolved)
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    fn library() -> (tempfile::TempDir, PathBuf) {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().join("library");
+        fs::create_dir_all(root.join("Movie")).unwrap();
+        fs::write(root.join("Movie/Movie.mp4"), "video").unwrap();
+        fs::write(dir.path().join("secret.txt"), "secret").unwrap();
+        (dir, root)
+    }
+
+    #[test]
+    fn serves_plain_files() {
+        let (_dir, root) = library();
+        for policy in [
+            SymlinkPolicy::Deny,
+            SymlinkPolicy::WithinRoot,
+            SymlinkPolicy::Follow,
+        ] {
+            assert!(resolve(&root, "Movie/Movie.mp4", policy).is_some());
+        }
+    }
+
+    #[test]
+    fn rejects_traversal() {
+        let (_dir, root) = library();
+        for path in [
+            "../secret.txt",
+            "Movie/../../secret.txt",
+            "./Movie/Movie.mp4",
+            "/etc/passwd",
+            "",
+            "Movie",
+            "Movie/missing.mp4",
+        ] {
+            for policy in [
+                SymlinkPolicy::Deny,
+                SymlinkPolicy::WithinRoot,
+                SymlinkPolicy::Follow,
+            ] {
+                assert_eq!(resolve(&root, path, policy), None, "{} {:?}", path, policy);
+            }
+        }
+    }
+
+    #[test]
+    fn config_names_match_the_command_line() {
+        #[derive(Deserialize)]
+        struct Config {
+            symlinks: SymlinkPolicy,
+        }
+        for policy in [
+            SymlinkPolicy::Deny,
+            SymlinkPolicy::WithinRoot,
+            SymlinkPolicy::Follow,
+        ] {
+            let name = policy.to_possible_value().unwrap().get_name().to_string();
+            let config: Config = toml::from_str(&format!("symlinks = \"{}\"", name)).unwrap();
+            assert_eq!(config.symlinks, policy);
+        }
+    }
+
+    #[test]
+    fn indexes_only_scanned_files() {
+        let movie = Movie {
+            movie: PathBuf::from("Movie/Movie.mp4"),
+            poster: vec![PathBuf::from("Movie/Movie-poster.jpg")],
+            ..Default::default()
+        };
+        let index = FileIndex::new(&[movie]);
+
+        assert_eq!(
+            index.get(Path::new("Movie/Movie.mp4")),
+            Some((FileKind::Video, 0))
+        );
+        assert_eq!(
+            index.get(Path::new("Movie/Movie-poster.jpg")),
+            Some((FileKind::Image, 0))
+        );
+        assert_eq!(index.get(Path::new("Movie/Movie.nfo")), None);
+        assert_eq!(index.get(Path::new("Movie/../Movie/Movie.mp4")), None);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn applies_symlink_policy() {
+        let (dir, root) = library();
+        std::os::unix::fs::symlink(dir.path().join("secret.txt"), root.join("Movie/out.mp4"))
+            .unwrap();
+        std::os::unix::fs::symlink(root.join("Movie/Movie.mp4"), root.join("Movie/in.mp4"))
+            .unwrap();
+
+        assert_eq!(resolve(&root, "Movie/out.mp4", SymlinkPolicy::Deny), None);
+        assert_eq!(resolve(&root, "Movie/in.mp4", SymlinkPolicy::Deny), None);
+
+        assert_eq!(
+            resolve(&root, "Movie/out.mp4", SymlinkPolicy::WithinRoot),
+            None
+        );
+        assert!(resolve(&root, "Movie/in.mp4", SymlinkPolicy::WithinRoot).is_some());
+
+        assert!(resolve(&root, "Movie/out.mp4", SymlinkPolicy::Follow).is_some());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn rejects_symlinked_folders() {
+        let (dir, root) = library();
+        std::os::unix::fs::symlink(dir.path(), root.join("Linked")).unwrap();
+
+        assert_eq!(
+            resolve(&root, "Linked/secret.txt", SymlinkPolicy::Deny),
+            None
+        );
+        assert_eq!(
+            resolve(&root, "Linked/secret.txt", SymlinkPolicy::WithinRoot),
+            None
+        );
+    }
+}
@@ -0,0 +1,127 @@
+use std::sync::Arc;
+
+use actix_web::{web, HttpResponse};
+use serde::Deserialize;
+use serde_json::json;
+
+use crate::Data;
+
+/// `?kiosk=true` on `/grid` and `/tv`, for screens nobody sits in front of.
+#[derive(Debug, Deserialize)]
+pub struct KioskQuery {
+    #[serde(default)]
+    pub kiosk: bool,
+}
+
+impl KioskQuery {
+    /// The class of the `<body>`.
+    pub fn class(&self) -> &'static str {
+        if self.kiosk {
+            "kiosk"
+        } else {
+            ""
+        }
+    }
+}
+
+/// No cursor, captions over the items and a hint how to get fullscreen.
+pub const KIOSK_STYLE: &str = r#"
+.caption {
+    display: none;
+}
+body.kiosk, body.kiosk * {
+    cursor: none !important;
+}
+body.kiosk a {
+    pointer-events: none;
+}
+body.kiosk .caption {
+    display: block;
+    position: absolute;
+    left: 0;
+    top: 0;
+    margin: 1vh;
+    padding: 0.3em 0.6em;
+    color: white;
+    background: rgba(0, 0, 0, 0.5);
+    font: 2vh sans-serif;
+    border-radius: 0.3em;
+    pointer-events: none;
+}
+#kiosk-hint {
+    display: none;
+    position: fixed;
+    left: 50%;
+    bottom: 5vh;
+    transform: translateX(-50%);
+    padding: 0.5em 1em;
+    color: white;
+    background: rgba(0, 0, 0, 0.7);
+    font: 2.5vh sans-serif;
+    border-radius: 0.3em;
+    z-index: 10;
+}
+body.kiosk #kiosk-hint.show {
+    display: block;
+}
+"#;
+
+/// Asks for fullscreen on the first click or key press and reloads the page once the
+/// server runs again after a restart, for new urls and a new library, or once the
+/// session expired.
+pub const KIOSK_SCRIPT: &str = r#"
+function startKiosk(started) {
+    if (!document.body.classList.contains('kiosk')) {
+        return;
+    }
+    const hint = document.createElement('div');
+    hint.id = 'kiosk-hint';
+    hint.textContent = 'Click or press any key for fullscreen (F11 in most browsers)';
+    document.body.appendChild(hint);
+    function updateHint() {
+        hint.classList.toggle('show', !document.fullscreenElement);
+    }
+    function fullscreen() {
+        if (!document.fullscreenElement && document.documentElement.requestFullscreen) {
+            document.documentElement.requestFullscreen().catch(function() {});
+        }
+    }
+    document.addEventListener('click', fullscreen);
+    document.addEventListener('keydown', fullscreen);
+    document.addEventListener('fullscreenchange', updateHint);
+    updateHint();
+    setTimeout(function() {
+        hint.classList.remove('show');
+    }, 15000);
+
+    setInterval(function() {
+        fetch('/api/instance', { cache: 'no-store' })
+            .then(function(response) {
+                // the session expired, the reload leads to the login
+                if (response.status === 401) {
+                    location.reload();
+                    return null;
+                }
+                return response.ok ? response.json() : null;
+            })
+            .then(function(instance) {
+                if (!instance) {
+                    return;
+                }
+                if (instance.started !== started) {
+                    location.reload();
+                }
+            })
+            .catch(function() {
+                // down for now, the next start reloads the page
+            });
+    }, 15000);
+}
+"#;
+
+/// `/api/instance`, changes with every server start.
+pub async fn instance(data: web::Data<Arc<Data>>) -> HttpResponse {
+    HttpResponse::Ok()
+        .insert_header(("Cache-Control", "no-store"))
+        .json(json!({ "started": data.started }))
+}
@@ -0,0 +1,668 @@
+use std::collections::HashMap;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use actix_web::http::header::{HeaderName, HeaderValue};
+use actix_web::http::StatusCode;
+use base64::Engine;
+use percent_encoding::{percent_decode_str, utf8_percent_encode, NON_ALPHANUMERIC};
+use serde::Deserialize;
+use serde_json::json;
+
+use crate::config::Config;
+use crate::library::{open_local, Library, LibrarySource, MediaFile, Remote};
+use crate::nfo::MovieInfo;
+use crate::store::Progress;
+use crate::subtitles::find_subtitles;
+use crate::Movie;
+
+/// Movies requested per call, large libraries are paged.
+const PAGE_SIZE: u64 = 500;
+const PROPERTIES: [&str; 15] = [
+    "title",
+    "originaltitle",
+    "year",
+    "tagline",
+    "plot",
+    "runtime",
+    "rating",
+    "mpaa",
+    "genre",
+    "director",
+    "file",
+    "art",
+    "playcount",
+    "resume",
+    "trailer",
+];
+
+#[derive(Debug, Deserialize)]
+struct RpcResponse<T> {
+    result: Option<T>,
+    error: Option<RpcError>,
+}
+
+#[derive(Debug, Deserialize)]
+struct RpcError {
+    code: i64,
+    message: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct MoviesResult {
+    #[serde(default)]
+    movies: Vec<KodiMovie>,
+    limits: Limits,
+}
+
+#[derive(Debug, Deserialize)]
+struct Limits {
+    end: u64,
+    total: u64,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct KodiMovie {
+    title: String,
+    originaltitle: String,
+    year: u32,
+    tagline: String,
+    plot: String,
+    /// seconds
+    runtime: u64,
+    rating: f64,
+    mpaa: String,
+    genre: Vec<String>,
+    director: Vec<String>,
+    file: String,
+    art: HashMap<String, String>,
+    playcount: u64,
+    resume: Resume,
+    trailer: String,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct Resume {
+    position: f64,
+    total: f64,
+}
+
+/// Reads the movies of a Kodi library over JSON-RPC. Kodi's paths below `prefix`
+/// are served from the same relative paths below the library directory.
+#[derive(Debug, Clone)]
+pub struct Kodi {
+    url: String,
+    authorization: Option<String>,
+    prefix: String,
+    root_dir: String,
+}
+
+fn non_empty(s: String) -> Option<String> {
+    if s.is_empty() {
+        None
+    } else {
+        Some(s)
+    }
+}
+
+impl Kodi {
+    pub fn new(url: &str, config: &Config) -> Kodi {
+        let url = if url.trim_end_matches('/').ends_with("/jsonrpc") {
+            url.to_string()
+        } else {
+            format!("{}/jsonrpc", url.trim_end_matches('/'))
+        };
+        let authorization = config.kodi_user.as_ref().map(|user| {
+            let credentials = format!(
+                "{}:{}",
+                user,
+                config.kodi_password.as_deref().unwrap_or_default()
+            );
+            format!(
+                "Basic {}",
+                base64::engine::general_purpose::STANDARD.encode(credentials)
+            )
+        });
+        let mut prefix = config
+            .kodi_path_prefix
+            .clone()
+            .unwrap_or_else(|| config.directory.clone());
+        // kodi separates with / even on windows for network shares
+        if !prefix.ends_with('/') && !prefix.ends_with('\\') {
+            prefix.push('/');
+        }
+        Kodi {
+            url,
+            authorization,
+            prefix,
+            root_dir: config.directory.clone(),
+        }
+    }
+
+    fn call<T: for<'de> Deserialize<'de>>(
+        &self,
+        method: &str,
+        params: serde_json::Value,
+    ) -> io::Result<T> {
+        let agent = ureq::Agent::new_with_config(
+            ureq::Agent::config_builder()
+                .timeout_global(Some(Duration::from_secs(60)))
+                .build(),
+        );
+        let mut request = agent.post(&self.url);
+        if let Some(authorization) = &self.authorization {
+            request = request.header("Authorization", authorization);
+        }
+        let response: RpcResponse<T> = request
+            .send_json(json!({
+                "jsonrpc": "2.0",
+                "method": method,
+                "params": params,
+                "id": 1,
+            }))
+            .and_then(|mut r| r.body_mut().read_json())
+            .map_err(|e| io::Error::other(format!("{}: {}", self.url, e)))?;
+        match (response.result, response.error) {
+            (Some(result), _) => Ok(result),
+            (None, Some(e)) => Err(io::Error::other(format!(
+                "{}: {} (code {})",
+                method, e.message, e.code
+            ))),
+            (None, None) => Err(io::Error::other(format!("{}: empty response", method))),
+        }
+    }
+
+    /// Kodi's web server serves its files below `/vfs/`.
+    fn vfs_url(&self, path: &str) -> String {
+        let path = if self.prefix.ends_with('\\') {
+            path.replace('/', "\\")
+        } else {
+            path.to_string()
+        };
+        format!(
+            "{}/vfs/{}",
+            self.url.trim_end_matches("/jsonrpc"),
+            utf8_percent_encode(&(self.prefix.clone() + &path), NON_ALPHANUMERIC)
+        )
+    }
+
+    /// Streams a file from Kodi, passing range requests on.
+    fn stream(&self, path: &str) -> MediaFile {
+        let url = self.vfs_url(path);
+        let authorization = self.authorization.clone();
+        let content_type = Path::new(path)
+            .extension()
+            .map(|e| actix_files::file_extension_to_mime(&e.to_string_lossy()).to_string())
+            .unwrap_or_else(|| "application/octet-stream".to_string());
+        tracing::debug!(url, "streaming from kodi");
+        MediaFile::Stream {
+            fetch: Box::new(move |range| {
+                let agent = ureq::Agent::new_with_config(
+                    ureq::Agent::config_builder()
+                        .http_status_as_error(false)
+                        .build(),
+                );
+                let mut request = agent.get(&url);
+                if let Some(authorization) = &authorization {
+                    request = request.header("Authorization", authorization);
+                }
+                if let Some(range) = &range {
+                    request = request.header("Range", range);
+                }
+                let response = request
+                    .call()
+                    .map_err(|e| io::Error::other(format!("{}: {}", url, e)))?;
+                let header = |name: &str| {
+                    let value = response.headers().get(name)?;
+                    Some((
+                        HeaderName::try_from(name).ok()?,
+                        HeaderValue::from_bytes(value.as_bytes()).ok()?,
+                    ))
+                };
+                let headers = ["Content-Range", "Accept-Ranges"]
+                    .into_iter()
+                    .filter_map(header)
+                    .collect();
+                let length = response
+                    .headers()
+                    .get("Content-Length")
+                    .and_then(|l| l.to_str().ok()?.parse().ok());
+                Ok(Remote {
+                    status: StatusCode::from_u16(response.status().as_u16())
+                        .unwrap_or(StatusCode::BAD_GATEWAY),
+                    headers,
+                    length,
+                    reader: Box::new(response.into_body().into_reader()),
+                })
+            }),
+            content_type,
+        }
+    }
+
+    /// Path below the library directory for a path as Kodi sees it, `None` for
+    /// files elsewhere, e.g. artwork Kodi only knows by url.
+    fn local_path(&self, kodi_path: &str) -> Option<PathBuf> {
+        let path = match kodi_path.strip_prefix("image://") {
+            Some(image) => percent_decode_str(image.trim_end_matches('/'))
+                .decode_utf8()
+                .ok()?
+                .to_string(),
+            None => kodi_path.to_string(),
+        };
+        let relative = path.strip_prefix(&self.prefix)?;
+        if relative.is_empty() {
+            None
+        } else {
+            Some(PathBuf::from(relative))
+        }
+    }
+
+    fn art(&self, movie: &KodiMovie, kinds: &[&str]) -> Vec<PathBuf> {
+        let mut keys: Vec<&String> = movie
+            .art
+            .keys()
+            .filter(|k| {
+                kinds.iter().any(|kind| {
+                    k.strip_prefix(kind)
+                        .is_some_and(|n| n.chars().all(|c| c.is_ascii_digit()))
+                })
+            })
+            .collect();
+        keys.sort();
+        keys.iter()
+            .filter_map(|k| self.local_path(&movie.art[*k]))
+            .collect()
+    }
+
+    fn to_movie(&self, m: KodiMovie) -> Option<(Movie, Option<Progress>)> {
+        // stack:///a/Movie-cd1.mp4 , /a/Movie-cd2.mp4 with commas in names doubled
+        let files: Vec<String> = match m.file.strip_prefix("stack://") {
+            Some(stack) => stack.split(" , ").map(|f| f.replace(",,", ",")).collect(),
+            None => vec![m.file.clone()],
+        };
+        let mut videos = Vec::new();
+        for file in files {
+            let playable = Path::new(&file)
+                .extension()
+                .map(|e| e.to_string_lossy().to_lowercase())
+                .is_some_and(|e| e == "mp4" || e == "webm");
+            match self.local_path(&file) {
+                Some(path) if playable => videos.push(path),
+                Some(_) => {
+                    tracing::debug!(file, "skipping unsupported container");
+                    return None;
+                }
+                None => {
+                    tracing::debug!(file, "skipping file outside of kodi_path_prefix");
+                    return None;
+                }
+            }
+        }
+        let mut videos = videos.into_iter();
+        let movie = videos.next()?;
+
+        let progress = if m.playcount > 0 || m.resume.position > 0.0 {
+            Some(Progress {
+                position: m.resume.position,
+                duration: m.resume.total,
+                updated: 0,
+                watched: m.playcount > 0,
+            })
+        } else {
+            None
+        };
+        let full_path = Path::new(&self.root_dir).join(&movie);
+        let movie = Movie {
+            parts: videos.collect(),
+            trailer: self.local_path(&m.trailer).into_iter().collect(),
+            poster: self.art(&m, &["poster"]),
+            thumb: self.art(&m, &["thumb"]),
+            fanarts: self.art(&m, &["fanart"]),
+            clearlogo: self.art(&m, &["clearlogo"]),
+            clearart: self.art(&m, &["clearart"]),
+            banner: self.art(&m, &["banner"]),
+            landscape: self.art(&m, &["landscape"]),
+            disc: self.art(&m, &["discart"]),
+            subtitles: find_subtitles(&self.root_dir, &full_path),
+            // files missing locally are streamed from kodi with unknown size
+            size: full_path.metadata().ok().map(|m| m.len()),
+            info: MovieInfo {
+                title: non_empty(m.title),
+                original_title: non_empty(m.originaltitle),
+                year: (m.year > 0).then(|| m.year.to_string()),
+                tagline: non_empty(m.tagline),
+                plot: non_empty(m.plot),
+                runtime: (m.runtime > 0).then(|| (m.runtime / 60).to_string()),
+                rating: (m.rating > 0.0).then(|| format!("{:.1}", m.rating)),
+                certification: non_empty(m.mpaa),
+                genres: m.genre,
+                directors: m.director,
+            },
+            movie,
+            ..Default::default()
+        };
+        Some((movie, progress))
+    }
+}
+
+impl LibrarySource for Kodi {
+    /// All playable movies and the watch state Kodi has for them.
+    fn load(&self) -> io::Result<Library> {
+        let start = std::time::Instant::now();
+        tracing::info!(url = %self.url, "loading library from kodi");
+        let mut movies = Vec::new();
+        let mut progress = Vec::new();
+        let mut offset = 0;
+        loop {
+            let page: MoviesResult = self.call(
+                "VideoLibrary.GetMovies",
+                json!({
+                    "properties": PROPERTIES,
+                    "limits": { "start": offset, "end": offset + PAGE_SIZE },
+                }),
+            )?;
+            for m in page.movies {
+                if let Some((movie, p)) = self.to_movie(m) {
+                    if let Some(p) = p {
+                        progress.push((movie.movie.to_string_lossy().to_string(), p));
+                    }
+                    movies.push(movie);
+                }
+            }
+            if page.limits.end >= page.limits.total || page.limits.end <= offset {
+                break;
+            }
+            offset = page.limits.end;
+        }
+        tracing::info!(
+            movies = movies.len(),
+            duration_ms = start.elapsed().as_millis() as u64,
+            "loaded library from kodi"
+        );
+        Ok(Library { movies, progress })
+    }
+
+    /// Files are read from the library directory, those missing there are streamed
+    /// from Kodi's web server.
+    fn open(&self, path: &str, config: &Config) -> io::Result<MediaFile> {
+        if Path::new(&self.root_dir)
+            .join(path)
+            .symlink_metadata()
+            .is_err()
+        {
+            return Ok(self.stream(path));
+        }
+        open_local(&self.root_dir, path, config)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::net::TcpListener;
+
+    /// Answers `requests` JSON-RPC calls with `response(request body)`.
+    fn mock_kodi(
+        requests: usize,
+        response: impl Fn(serde_json::Value) -> serde_json::Value + Send + 'static,
+    ) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        std::thread::spawn(move || {
+            for stream in listener.incoming().take(requests) {
+                let mut stream = stream.unwrap();
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+                let mut length = 0;
+                let mut authorization = String::new();
+                loop {
+                    let mut line = String::new();
+                    reader.read_line(&mut line).unwrap();
+                    let lower = line.to_lowercase();
+                    if let Some(l) = lower.strip_prefix("content-length:") {
+                        length = l.trim().parse().unwrap();
+                    }
+                    if lower.starts_with("authorization:") {
+                        authorization = line[14..].trim().to_string();
+                    }
+                    if line.trim().is_empty() {
+                        break;
+                    }
+                }
+                let mut body = vec![0; length];
+                reader.read_exact(&mut body).unwrap();
+                let mut request: serde_json::Value = serde_json::from_slice(&body).unwrap();
+                request["authorization"] = json!(authorization);
+                let body = response(request).to_string();
+                write!(
+                    stream,
+                    "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                    body.len(),
+                    body
+                )
+                .unwrap();
+            }
+        });
+        url
+    }
+
+    /// Serves `content` once, the range `bytes=<start>-<end>` if requested.
+    fn mock_vfs(content: &'static [u8]) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut range = None;
+            loop {
+                let mut line = String::new();
+                reader.read_line(&mut line).unwrap();
+                if let Some(r) = line.to_lowercase().strip_prefix("range: bytes=") {
+                    let (start, end) = r.trim().split_once('-').unwrap();
+                    range = Some((
+                        start.parse::<usize>().unwrap(),
+                        end.parse::<usize>().unwrap(),
+                    ));
+                }
+                if line.trim().is_empty() {
+                    break;
+                }
+            }
+            let head = match range {
+                Some((start, end)) => format!(
+                    "HTTP/1.1 206 Partial Content\r\nContent-Range: bytes {}-{}/{}\r\nContent-Length: {}\r\n",
+                    start,
+                    end,
+                    content.len(),
+                    end + 1 - start
+                ),
+                None => format!("HTTP/1.1 200 OK\r\nContent-Length: {}\r\n", content.len()),
+            };
+            let (start, end) = range.unwrap_or((0, content.len() - 1));
+            write!(
+                stream,
+                "{}Accept-Ranges: bytes\r\nConnection: close\r\n\r\n",
+                head
+            )
+            .unwrap();
+            stream.write_all(&content[start..=end]).unwrap();
+        });
+        url
+    }
+
+    #[actix_web::test]
+    async fn passes_range_requests_to_kodi() {
+        use actix_web::body::MessageBody;
+        use actix_web::test::TestRequest;
+
+        let url = mock_vfs(b"0123456789");
+        let kodi = Kodi::new(&url, &config(&url));
+        let file = kodi.open("Foo/Foo.mp4", &config(&url)).unwrap();
+        let req = TestRequest::default()
+            .insert_header(("Range", "bytes=2-5"))
+            .to_http_request();
+        let res = file.into_response(&req).await;
+        assert_eq!(res.status(), StatusCode::PARTIAL_CONTENT);
+        assert_eq!(res.headers().get("Content-Range").unwrap(), "bytes 2-5/10");
+        assert_eq!(res.headers().get("Accept-Ranges").unwrap(), "bytes");
+        assert_eq!(res.headers().get("Content-Type").unwrap(), "video/mp4");
+        let body = res.into_body();
+        assert_eq!(body.size(), actix_web::body::BodySize::Sized(4));
+        assert_eq!(actix_web::body::to_bytes(body).await.unwrap(), "2345");
+
+        let url = mock_vfs(b"0123456789");
+        let file = Kodi::new(&url, &config(&url))
+            .open("Foo/Foo.mp4", &config(&url))
+            .unwrap();
+        assert_eq!(file.into_bytes().await.unwrap(), "0123456789");
+    }
+
+    fn config(url: &str) -> Config {
+        Config {
+            directory: "/library/".to_string(),
+            kodi_url: Some(url.to_string()),
+            kodi_path_prefix: Some("/mnt/movies".to_string()),
+            kodi_user: Some("kodi".to_string()),
+            kodi_password: Some("secret".to_string()),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn streams_missing_files_from_kodi() {
+        let kodi = Kodi::new("http://kodi:8080/", &config("http://kodi:8080"));
+        assert_eq!(
+            kodi.vfs_url("Foo (2000)/Foo (2000).mp4"),
+            "http://kodi:8080/vfs/%2Fmnt%2Fmovies%2FFoo%20%282000%29%2FFoo%20%282000%29%2Emp4"
+        );
+        let file = kodi
+            .open("Foo (2000)/Foo (2000).mp4", &config("http://kodi:8080"))
+            .unwrap();
+        assert!(
+            matches!(file, MediaFile::Stream { ref content_type, .. } if content_type == "video/mp4")
+        );
+    }
+
+    #[test]
+    fn maps_kodi_movies() {
+        let url = mock_kodi(1, |request| {
+            assert_eq!(request["method"], "VideoLibrary.GetMovies");
+            assert_eq!(request["authorization"], "Basic a29kaTpzZWNyZXQ=");
+            json!({
+                "jsonrpc": "2.0",
+                "id": 1,
+                "result": {
+                    "limits": { "start": 0, "end": 3, "total": 3 },
+                    "movies": [
+                        {
+                            "movieid": 1,
+                            "title": "Foo",
+                            "year": 2000,
+                            "runtime": 5400,
+                            "rating": 7.25,
+                            "mpaa": "Rated PG-13",
+                            "genre": ["Comedy"],
+                            "director": ["Jane Doe"],
+                            "file": "/mnt/movies/Foo (2000)/Foo (2000).mp4",
+                            "trailer": "plugin://plugin.video.youtube/?video_id=x",
+                            "art": {
+                                "poster": "image://%2fmnt%2fmovies%2fFoo%20(2000)%2fFoo%20(2000)-poster.jpg/",
+                                "fanart": "image://https%3a%2f%2fimage.tmdb.org%2ffanart.jpg/",
+                                "fanart1": "image://%2fmnt%2fmovies%2fFoo%20(2000)%2fextrafanart%2f1.jpg/",
+                                "clearlogo": "image://%2fmnt%2fmovies%2fFoo%20(2000)%2fclearlogo.png/"
+                            },
+                            "playcount": 1,
+                            "resume": { "position": 0, "total": 0 }
+                        },
+                        {
+                            "movieid": 2,
+                            "title": "Stacked",
+                            "file": "stack:///mnt/movies/Stacked/Stacked-cd1.mp4 , /mnt/movies/Stacked/Stacked,,-cd2.mp4",
+                            "resume": { "position": 600, "total": 3000 }
+                        },
+                        {
+                            "movieid": 3,
+                            "title": "Unplayable",
+                            "file": "/mnt/movies/Unplayable/Unplayable.mkv"
+                        }
+                    ]
+                }
+            })
+        });
+        let Library { movies, progress } = Kodi::new(&url, &config(&url)).load().unwrap();
+
+        assert_eq!(movies.len(), 2);
+        let foo = &movies[0];
+        assert_eq!(foo.movie, PathBuf::from("Foo (2000)/Foo (2000).mp4"));
+        assert_eq!(
+            foo.poster,
+            vec![PathBuf::from("Foo (2000)/Foo (2000)-poster.jpg")]
+        );
+        assert_eq!(
+            foo.fanarts,
+            vec![PathBuf::from("Foo (2000)/extrafanart/1.jpg")]
+        );
+        assert_eq!(
+            foo.clearlogo,
+            vec![PathBuf::from("Foo (2000)/clearlogo.png")]
+        );
+        assert!(foo.trailer.is_empty());
+        assert_eq!(foo.info.title.as_deref(), Some("Foo"));
+        assert_eq!(foo.info.year.as_deref(), Some("2000"));
+        assert_eq!(foo.info.runtime.as_deref(), Some("90"));
+        assert_eq!(foo.info.rating.as_deref(), Some("7.2"));
+        assert_eq!(foo.info.certification.as_deref(), Some("Rated PG-13"));
+        assert_eq!(foo.info.genres, vec!["Comedy"]);
+
+        let stacked = &movies[1];
+        assert_eq!(stacked.movie, PathBuf::from("Stacked/Stacked-cd1.mp4"));
+        assert_eq!(
+            stacked.parts,
+            vec![PathBuf::from("Stacked/Stacked,-cd2.mp4")]
+        );
+
+        assert_eq!(progress.len(), 2);
+        assert_eq!(progress[0].0, "Foo (2000)/Foo (2000).mp4");
+        assert!(progress[0].1.watched);
+        assert_eq!(progress[1].1.position, 600.0);
+        assert!(!progress[1].1.watched);
+    }
+
+    #[test]
+    fn pages_large_libraries() {
+        let url = mock_kodi(2, |request| {
+            let start = request["params"]["limits"]["start"].as_u64().unwrap();
+            let movie = format!("/mnt/movies/M{0}/M{0}.mp4", start);
+            json!({
+                "jsonrpc": "2.0",
+                "id": 1,
+                "result": {
+                    "limits": { "start": start, "end": start + PAGE_SIZE, "total": PAGE_SIZE + 1 },
+                    "movies": [{ "title": "M", "file": movie }]
+                }
+            })
+        });
+        let movies = Kodi::new(&url, &config(&url)).load().unwrap().movies;
+        assert_eq!(movies.len(), 2);
+        assert_eq!(
+            movies[1].movie,
+            PathBuf::from(format!("M{0}/M{0}.mp4", PAGE_SIZE))
+        );
+    }
+
+    #[test]
+    fn reports_rpc_errors() {
+        let url = mock_kodi(1, |_| {
+            json!({
+                "jsonrpc": "2.0",
+                "id": 1,
+                "error": { "code": -32601, "message": "Method not found." }
+            })
+        });
+        let e = Kodi::new(&url, &config(&url)).load().unwrap_err();
+        assert!(e.to_string().contains("Method not found."), "{}", e);
+    }
+}
@@ -0,0 +1,233 @@
+use std::fmt::{self, Debug};
+use std::io::{self, Read};
+use std::path::Path;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use actix_files::NamedFile;
+use actix_web::body::{BodySize, BoxBody, MessageBody};
+use actix_web::http::header::{self, HeaderName, HeaderValue};
+use actix_web::http::StatusCode;
+use actix_web::web::{self, Bytes};
+use actix_web::{HttpRequest, HttpResponse};
+use tokio::sync::mpsc;
+
+use crate::config::Config;
+use crate::files;
+use crate::kodi::Kodi;
+use crate::naming::Naming;
+use crate::store::Progress;
+use crate::{load_movie_data, Movie};
+
+/// Movies and the watch state another player recorded for them.
+#[derive(Debug, Default)]
+pub struct Library {
+    pub movies: Vec<Movie>,
+    pub progress: Vec<(String, Progress)>,
+}
+
+/// A file of a movie as the source provides it.
+pub enum MediaFile {
+    /// A local file, served with range requests and conditional headers.
+    Local(Box<NamedFile>),
+    /// A file fetched from another server once the request is known.
+    Stream { fetch: Fetch, content_type: String },
+}
+
+/// Fetches a file, called on the blocking thread pool with the Range header of the request.
+pub type Fetch = Box<dyn FnOnce(Option<String>) -> io::Result<Remote> + Send>;
+
+/// Another server's answer for a file.
+pub struct Remote {
+    pub status: StatusCode,
+    /// Headers passed on to the client, e.g. Content-Range.
+    pub headers: Vec<(HeaderName, HeaderValue)>,
+    pub length: Option<u64>,
+    pub reader: Box<dyn Read + Send>,
+}
+
+impl Debug for MediaFile {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MediaFile::Local(file) => f.debug_tuple("Local").field(&file.path()).finish(),
+            MediaFile::Stream { content_type, .. } => f
+                .debug_struct("Stream")
+                .field("content_type", content_type)
+                .finish_non_exhaustive(),
+        }
+    }
+}
+
+impl MediaFile {
+    pub async fn into_response(self, req: &HttpRequest) -> HttpResponse {
+        let (fetch, content_type) = match self {
+            MediaFile::Local(file) => return (*file).into_response(req),
+            MediaFile::Stream {
+                fetch,
+                content_type,
+            } => (fetch, content_type),
+        };
+        let range = req
+            .headers()
+            .get(header::RANGE)
+            .and_then(|r| r.to_str().ok())
+            .map(str::to_string);
+        let remote = match web::block(move || fetch(range))
+            .await
+            .map_err(|e| io::Error::other(e.to_string()))
+            .and_then(|r| r)
+        {
+            Ok(remote) => remote,
+            Err(e) => {
+                tracing::warn!(error = %e, "fetching a file failed");
+                return HttpResponse::BadGateway().finish();
+            }
+        };
+        let mut res = HttpResponse::build(remote.status);
+        if !remote.status.is_success() {
+            return res.finish();
+        }
+        res.content_type(content_type);
+        for header in remote.headers {
+            res.insert_header(header);
+        }
+        res.body(blocking_body(remote.length, remote.reader))
+    }
+
+    /// The whole content, for files that are converted before serving.
+    pub async fn into_bytes(self) -> io::Result<Bytes> {
+        web::block(move || {
+            let mut content = Vec::new();
+            match self {
+                MediaFile::Local(file) => file.file().read_to_end(&mut content)?,
+                MediaFile::Stream { fetch, .. } => {
+                    let mut remote = fetch(None)?;
+                    if !remote.status.is_success() {
+                        return Err(io::Error::other(remote.status.to_string()));
+                    }
+                    remote.reader.read_to_end(&mut content)?
+                }
+            };
+            Ok(Bytes::from(content))
+        })
+        .await
+        .map_err(|e| io::Error::other(e.to_string()))?
+    }
+}
+
+/// Chunks read by a thread, the body of a [`MediaFile::Stream`] from a blocking reader.
+struct ChannelBody {
+    chunks: mpsc::Receiver<io::Result<Bytes>>,
+    length: Option<u64>,
+}
+
+impl MessageBody for ChannelBody {
+    type Error = io::Error;
+
+    fn size(&self) -> BodySize {
+        self.length.map_or(BodySize::Stream, BodySize::Sized)
+    }
+
+    fn poll_next(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Bytes, Self::Error>>> {
+        self.get_mut().chunks.poll_recv(cx)
+    }
+}
+
+/// Streams a blocking reader of `length` bytes if known, read on the blocking thread pool
+/// so slow servers don't block the workers. Stops when the client goes away.
+pub fn blocking_body(length: Option<u64>, mut reader: impl Read + Send + 'static) -> BoxBody {
+    let (tx, rx) = mpsc::channel(4);
+    actix_web::rt::task::spawn_blocking(move || {
+        let mut buffer = vec![0; 64 * 1024];
+        loop {
+            let chunk = match reader.read(&mut buffer) {
+                Ok(0) => return,
+                Ok(n) => Ok(Bytes::copy_from_slice(&buffer[..n])),
+                Err(e) => Err(e),
+            };
+            let failed = chunk.is_err();
+            if tx.blocking_send(chunk).is_err() || failed {
+                return;
+            }
+        }
+    });
+    ChannelBody { chunks: rx, length }.boxed()
+}
+
+/// Where the movies come from and how their files are read. The handlers only see
+/// `Movie`s and paths relative to the library.
+pub trait LibrarySource: Debug + Send + Sync {
+    /// All movies, called once at startup.
+    fn load(&self) -> io::Result<Library>;
+
+    /// Opens a file of a movie by its path relative to the library. `config` is the
+    /// current one, it changes on reload.
+    fn open(&self, path: &str, config: &Config) -> io::Result<MediaFile>;
+}
+
+/// The source selected by the config, Kodi if `kodi_url` is set.
+pub fn from_config(config: &Config) -> Box<dyn LibrarySource> {
+    match &config.kodi_url {
+        Some(url) => Box::new(Kodi::new(url, config)),
+        None => Box::new(Filesystem {
+            root_dir: config.directory.clone(),
+            naming: config.naming,
+        }),
+    }
+}
+
+/// A file below the library directory, following the symlink policy.
+pub fn open_local(root_dir: &str, path: &str, config: &Config) -> io::Result<MediaFile> {
+    let full = files::resolve(Path::new(root_dir), path, config.symlinks)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, path.to_string()))?;
+    NamedFile::open(full).map(|file| MediaFile::Local(Box::new(file)))
+}
+
+/// Scans the folders of the library directory.
+#[derive(Debug, Clone)]
+pub struct Filesystem {
+    root_dir: String,
+    naming: Naming,
+}
+
+impl LibrarySource for Filesystem {
+    fn load(&self) -> io::Result<Library> {
+        Ok(Library {
+            movies: load_movie_data(&self.root_dir, self.naming),
+            progress: Vec::new(),
+        })
+    }
+
+    fn open(&self, path: &str, config: &Config) -> io::Result<MediaFile> {
+        open_local(&self.root_dir, path, config)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::body;
+
+    #[actix_web::test]
+    async fn streams_blocking_readers() {
+        let content: Vec<u8> = (0..200_000).map(|i| i as u8).collect();
+        let body = blocking_body(None, io::Cursor::new(content.clone()));
+        assert_eq!(body.size(), BodySize::Stream);
+        assert_eq!(body::to_bytes(body).await.unwrap(), content);
+
+        let body = blocking_body(Some(3), io::Cursor::new(b"abc".to_vec()));
+        assert_eq!(body.size(), BodySize::Sized(3));
+        assert_eq!(body::to_bytes(body).await.unwrap(), "abc");
+
+        struct Failing;
+        impl Read for Failing {
+            fn read(&mut self, _: &mut [u8]) -> io::Result<usize> {
+                Err(io::Error::other("unreachable"))
+            }
+        }
+        assert!(body::to_bytes(blocking_body(None, Failing)).await.is_err());
+    }
+}
@@ -0,0 +1,131 @@
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::Instant;
+
+use actix_web::body::{BodySize, BoxBody, MessageBody};
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::middleware::Next;
+use actix_web::web::Bytes;
+use actix_web::{Error, HttpMessage};
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+use tracing_subscriber::EnvFilter;
+
+use crate::auth::Profile;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, ValueEnum)]
+#[serde(rename_all = "snake_case")]
+pub enum LogFormat {
+    #[default]
+    Text,
+    Json,
+}
+
+/// `level` is a tracing filter like `info` or `random_video_server=debug`,
+/// `RUST_LOG` takes precedence.
+pub fn init(level: &str, format: LogFormat) {
+    let filter = EnvFilter::try_from_default_env()
+        .or_else(|_| EnvFilter::try_new(level))
+        .unwrap_or_else(|_| EnvFilter::new("info"));
+    let builder = tracing_subscriber::fmt()
+        .with_env_filter(filter)
+        .with_writer(std::io::stderr);
+    match format {
+        LogFormat::Text => builder.init(),
+        LogFormat::Json => builder.json().init(),
+    }
+}
+
+/// One access log line, written when the body is done or the client went away.
+struct AccessLog {
+    start: Instant,
+    method: String,
+    path: String,
+    status: u16,
+    peer: String,
+    profile: Option<String>,
+}
+
+/// Body counting the bytes actually sent, e.g. of a video the player stopped early.
+struct LoggedBody {
+    body: BoxBody,
+    bytes: u64,
+    log: AccessLog,
+}
+
+impl Drop for LoggedBody {
+    fn drop(&mut self) {
+        let log = &self.log;
+        tracing::info!(
+            target: "access",
+            method = log.method,
+            path = log.path,
+            status = log.status,
+            bytes = self.bytes,
+            duration_ms = log.start.elapsed().as_millis() as u64,
+            peer = log.peer,
+            profile = log.profile,
+            "request"
+        );
+    }
+}
+
+impl MessageBody for LoggedBody {
+    type Error = Box<dyn std::error::Error>;
+
+    fn size(&self) -> BodySize {
+        self.body.size()
+    }
+
+    fn poll_next(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Bytes, Self::Error>>> {
+        let this = self.get_mut();
+        let poll = Pin::new(&mut this.body).poll_next(cx);
+        if let Poll::Ready(Some(Ok(bytes))) = &poll {
+            this.bytes += bytes.len() as u64;
+        }
+        poll
+    }
+}
+
+/// Logs every request with status, bytes served and duration until the body is done.
+/// The query is left out since it may carry tokens or url signatures.
+pub async fn access_log(
+    req: ServiceRequest,
+    next: Next<BoxBody>,
+) -> Result<ServiceResponse<BoxBody>, Error> {
+    let start = Instant::now();
+    let method = req.method().to_string();
+    let path = req.path().to_string();
+    let peer = req
+        .connection_info()
+        .realip_remote_addr()
+        .unwrap_or_default()
+        .to_string();
+
+    let res = next.call(req).await?;
+
+    let profile = res
+        .request()
+        .extensions()
+        .get::<Profile>()
+        .map(|p| p.0.clone());
+    let log = AccessLog {
+        start,
+        method,
+        path,
+        status: res.status().as_u16(),
+        peer,
+        profile,
+    };
+    Ok(res.map_body(|_, body| {
+        LoggedBody {
+            body,
+            bytes: 0,
+            log,
+        }
+        .boxed()
+    }))
+}
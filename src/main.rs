@@ -1,100 +1,219 @@
-use actix_files::NamedFile;
-use actix_web::{web, App, HttpResponse, HttpServer, Responder, Result};
-use clap::Parser;
+mod ambient;
+mod auth;
+mod config;
+mod dlna;
+mod extras;
+mod files;
+mod kiosk;
+mod kodi;
+mod library;
+mod logging;
+mod metrics;
+mod naming;
+mod nfo;
+mod parental;
+mod playlist;
+mod reload;
+mod remote;
+mod report;
+mod store;
+mod subtitles;
+mod tls;
+
+use actix_web::body::BoxBody;
+use actix_web::{middleware, web, App, HttpRequest, HttpResponse, HttpServer, Responder, Result};
+use auth::{Auth, Profile, UrlSigner, DEFAULT_PROFILE};
+use clap::{Parser, Subcommand};
+use config::{Channel, Config, ConfigArgs};
+use dlna::Dlna;
+use extras::{find_extras, in_extras_folder, Extra, ExtraKind};
+use files::{FileIndex, FileKind};
 use glob::glob;
+use kiosk::KioskQuery;
+use library::{Library, LibrarySource, MediaFile};
+use metrics::{Metrics, StreamBody};
+use naming::{Naming, NamingConvention};
 use rand::seq::SliceRandom;
+use rand::Rng;
+use std::collections::{BTreeMap, BTreeSet};
 use std::fs::read_dir;
-use std::fs::read_to_string;
 use std::io;
 use std::path::Path;
 use std::path::PathBuf;
-use std::sync::Arc;
-use walkdir::WalkDir;
+use std::sync::{Arc, RwLock};
 
-use clap;
+use nfo::MovieInfo;
+use parental::Restriction;
+use percent_encoding::{utf8_percent_encode, AsciiSet, CONTROLS, NON_ALPHANUMERIC};
 use serde::Deserialize;
+use serde_json::json;
+use store::{FlagsUpdate, Store};
+use subtitles::{find_subtitles, to_webvtt, Subtitle};
 
-#[derive(Debug, Clone)]
+#[derive(Debug)]
 struct Data {
     movies: Vec<Movie>,
-    config: Config,
+    files: FileIndex,
+    library: Box<dyn LibrarySource>,
+    dlna: Option<Arc<Dlna>>,
+    screens: remote::Screens,
+    /// Milliseconds since the epoch at startup, kiosk pages reload when it changes.
+    started: u64,
+    /// Replaced when the config file changes.
+    config: RwLock<Arc<Config>>,
+    store: Arc<Store>,
+    auth: Option<Arc<Auth>>,
+    signer: Option<Arc<UrlSigner>>,
+    metrics: Arc<Metrics>,
 }
 
-#[derive(Debug, Deserialize, Clone)]
-struct Config {
-    directory: String,
-    ip_bind: String,
-    port_bind: u16,
-    trailer_factor: i8,
-    poster_factor: i8,
-    thumb_factor: i8,
-    fanart_factor: i8,
-    video_factor: i8,
+impl Data {
+    fn config(&self) -> Arc<Config> {
+        self.config.read().unwrap().clone()
+    }
 }
 
-#[derive(Debug, Deserialize, Parser)]
+#[derive(Debug, Parser)]
 #[clap(
     name = "OptConfig",
     about = "A configuration for the random video server."
 )]
 struct OptConfig {
-    #[arg(short, long, help = "Kodi videos directory")]
-    directory: Option<String>,
-
+    #[command(flatten)]
+    options: ConfigArgs,
     #[arg(
         short,
         long,
-        help = "IP for bind. 127.0.0.1 for only same machine. 0.0.0.0 for global access (default: 127.0.0.1)"
+        env = "RVS_CONFIG",
+        help = "Path to config file [default: $XDG_CONFIG_HOME/random_video_server/config.toml]"
     )]
-    ip_bind: Option<String>,
-
-    #[arg(short, long, help = "Port for bind (default: 3070)")]
-    port_bind: Option<u16>,
-
+    config: Option<PathBuf>,
     #[arg(
-        short,
         long,
-        help = "Path to config file [default: $XDG_CONFIG_HOME/random_video_server/config.toml]"
+        help = "Read a password from stdin and print its hash for the users file"
     )]
-    config: Option<PathBuf>,
-    #[arg(long, help = "Show trailers N-times more likely (default: 1)")]
-    trailer_factor: Option<i8>,
-    #[arg(long, help = "Show posters N-times more likely (default: 1)")]
-    poster_factor: Option<i8>,
-    #[arg(long, help = "Show thumb N-times more likely (default: 1)")]
-    thumb_factor: Option<i8>,
-    #[arg(long, help = "Show fanart N-times more likely (default: 1)")]
-    fanart_factor: Option<i8>,
-    #[arg(long, help = "Show video N-times more likely (default: 0)")]
-    video_factor: Option<i8>,
+    hash_password: bool,
+    #[command(subcommand)]
+    command: Option<Command>,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// Scan the library and exit without starting the server
+    Scan {
+        #[arg(long, help = "Print why folders are missing or incomplete")]
+        report: bool,
+    },
+    /// Inspect the configuration
+    Config {
+        #[command(subcommand)]
+        action: ConfigCommand,
+    },
+}
+
+#[derive(Debug, Subcommand)]
+enum ConfigCommand {
+    /// Validate the configuration and exit
+    Check,
+    /// Print the effective configuration as toml
+    Dump,
+}
+
+/// Another file of a movie, a stack next to a single file keeps its parts.
+#[derive(Debug, Clone, Default)]
+struct Version {
+    path: PathBuf,
+    parts: Vec<PathBuf>,
+}
+
+impl Version {
+    fn files(&self) -> impl Iterator<Item = &PathBuf> {
+        std::iter::once(&self.path).chain(&self.parts)
+    }
+}
+
+#[derive(Debug, Clone, Default)]
 struct Movie {
+    /// The main version, or the first part of a stacked movie.
     movie: PathBuf,
+    /// Size of `movie` in bytes when scanned, none if it isn't a local file.
+    size: Option<u64>,
+    /// Further parts played after `movie`, e.g. `-cd2`.
+    parts: Vec<PathBuf>,
+    /// Alternative files of the same movie, e.g. `- 1080p` or `-extended`.
+    versions: Vec<Version>,
     trailer: Vec<PathBuf>,
     poster: Vec<PathBuf>,
     thumb: Vec<PathBuf>,
     fanarts: Vec<PathBuf>,
+    clearlogo: Vec<PathBuf>,
+    clearart: Vec<PathBuf>,
+    banner: Vec<PathBuf>,
+    landscape: Vec<PathBuf>,
+    disc: Vec<PathBuf>,
+    subtitles: Vec<Subtitle>,
+    /// Videos from the extras folders next to the movie.
+    extras: Vec<Extra>,
+    info: MovieInfo,
+}
+
+impl Movie {
+    /// `-trailer` files and the videos of a `trailers/` folder.
+    fn trailers(&self) -> impl Iterator<Item = &PathBuf> {
+        self.trailer.iter().chain(
+            self.extras
+                .iter()
+                .filter(|e| e.kind == ExtraKind::Trailer)
+                .map(|e| &e.path),
+        )
+    }
+
+    fn title(&self) -> String {
+        self.info.title.clone().unwrap_or_else(|| {
+            self.movie
+                .file_stem()
+                .map(|s| s.to_string_lossy().to_string())
+                .unwrap_or_default()
+        })
+    }
+
+    /// `Title (2000)`
+    fn title_with_year(&self) -> String {
+        match &self.info.year {
+            Some(year) => format!("{} ({})", self.title(), year),
+            None => self.title(),
+        }
+    }
 }
 
 #[derive(Debug, Clone)]
 enum PathType {
     Video,
     Trailer,
+    Extra,
     Poster,
     Thumb,
     Fanart,
+    Clearlogo,
+    Clearart,
+    Banner,
+    Landscape,
+    Disc,
 }
 
 fn get_random_path(config: &Config, movie: &Movie) -> Option<(PathBuf, PathType)> {
     let mut paths = vec![];
 
     for _ in 0..config.trailer_factor {
-        for trailer in &movie.trailer {
+        for trailer in movie.trailers() {
             paths.push((trailer.clone(), PathType::Trailer));
         }
     }
+    for _ in 0..config.extra_factor {
+        for extra in movie.extras.iter().filter(|e| e.kind != ExtraKind::Trailer) {
+            paths.push((extra.path.clone(), PathType::Extra));
+        }
+    }
     for _ in 0..config.poster_factor {
         for poster in &movie.poster {
             paths.push((poster.clone(), PathType::Poster));
@@ -110,6 +229,27 @@ fn get_random_path(config: &Config, movie: &Movie) -> Option<(PathBuf, PathType)
             paths.push((fanart.clone(), PathType::Fanart));
         }
     }
+    for (factor, art, path_type) in [
+        (
+            config.clearlogo_factor,
+            &movie.clearlogo,
+            PathType::Clearlogo,
+        ),
+        (config.clearart_factor, &movie.clearart, PathType::Clearart),
+        (config.banner_factor, &movie.banner, PathType::Banner),
+        (
+            config.landscape_factor,
+            &movie.landscape,
+            PathType::Landscape,
+        ),
+        (config.disc_factor, &movie.disc, PathType::Disc),
+    ] {
+        for _ in 0..factor {
+            for path in art {
+                paths.push((path.clone(), path_type.clone()));
+            }
+        }
+    }
     for _ in 0..config.video_factor {
         paths.push((movie.movie.clone(), PathType::Video));
     }
@@ -118,15 +258,161 @@ fn get_random_path(config: &Config, movie: &Movie) -> Option<(PathBuf, PathType)
     paths.choose(&mut rng).cloned()
 }
 
+#[derive(Debug, Deserialize)]
+struct ChannelQuery {
+    channel: Option<String>,
+}
+
+fn find_channel(data: &Data, name: &Option<String>) -> Option<Channel> {
+    let name = name.as_ref()?;
+    data.config()
+        .channels
+        .iter()
+        .find(|c| &c.name == name)
+        .cloned()
+}
+
+/// Certifications the profile may see, the user's own limit or the global one.
+fn profile_restriction(data: &Data, profile: &Profile) -> Restriction {
+    let config = data.config();
+    let max_certification = data
+        .auth
+        .as_ref()
+        .and_then(|a| a.user(&profile.0))
+        .and_then(|u| u.max_certification.clone())
+        .or(config.max_certification.clone());
+    Restriction::new(max_certification.as_deref(), config.allow_unrated)
+}
+
+fn is_allowed(data: &Data, profile: &Profile, channel: Option<&Channel>, movie: &Movie) -> bool {
+    let mut restriction = profile_restriction(data, profile);
+    if let Some(channel) = channel {
+        restriction = restriction.and(&Restriction::new(
+            channel.max_certification.as_deref(),
+            data.config().allow_unrated,
+        ));
+        if !channel.genres.is_empty()
+            && !movie
+                .info
+                .genres
+                .iter()
+                .any(|g| channel.genres.iter().any(|c| c.eq_ignore_ascii_case(g)))
+        {
+            return false;
+        }
+    }
+    restriction.allows(&movie.info)
+}
+
+/// Resolves the path of a file route. Only files found by the scanner are served and every
+/// failure looks the same to the client, also for movies above the profile's certification
+/// limit.
+fn library_file(
+    data: &Data,
+    profile: &Profile,
+    kind: FileKind,
+    path: &str,
+) -> Result<MediaFile, actix_web::Error> {
+    let not_found = || actix_web::error::ErrorNotFound("Not found");
+    match data.files.get(Path::new(path)) {
+        Some((k, i)) if k == kind && is_allowed(data, profile, None, &data.movies[i]) => {}
+        _ => return Err(not_found()),
+    }
+    data.library
+        .open(path, &data.config())
+        .map_err(|_| not_found())
+}
+
+/// How likely a movie is picked, based on the user's flags. Hidden and restricted movies are never picked.
+fn movie_weight(data: &Data, profile: &Profile, channel: Option<&Channel>, movie: &Movie) -> f64 {
+    if !is_allowed(data, profile, channel, movie) {
+        return 0.0;
+    }
+    let flags = data.store.flags(&profile.0, &movie.movie.to_string_lossy());
+    if flags.hidden {
+        0.0
+    } else if flags.favorite {
+        data.config().favorite_factor.max(1) as f64
+    } else if flags.disliked {
+        1.0 / data.config().disliked_factor.max(1) as f64
+    } else {
+        1.0
+    }
+}
+
+/// The movies `/tv` picks from, unwatched ones only if preferred and there are any left.
+fn tv_movies<'a>(data: &'a Data, profile: &Profile, channel: Option<&Channel>) -> Vec<&'a Movie> {
+    let movies: Vec<&Movie> = data
+        .movies
+        .iter()
+        .filter(|m| movie_weight(data, profile, channel, m) > 0.0)
+        .collect();
+    if data.config().prefer_unwatched {
+        let unwatched: Vec<&Movie> = movies
+            .iter()
+            .filter(|m| {
+                !data
+                    .store
+                    .is_watched(&profile.0, &m.movie.to_string_lossy())
+            })
+            .copied()
+            .collect();
+        if !unwatched.is_empty() {
+            return unwatched;
+        }
+    }
+    movies
+}
+
+/// `tv_movies` in a random order where favorites come early more often, the same odds
+/// `/tv` picks with.
+fn shuffled_tv_movies<'a>(
+    data: &'a Data,
+    profile: &Profile,
+    channel: Option<&Channel>,
+) -> Vec<&'a Movie> {
+    let mut rng = rand::thread_rng();
+    // weighted shuffle: sort by u^(1/weight)
+    let mut movies: Vec<(&Movie, f64)> = tv_movies(data, profile, channel)
+        .into_iter()
+        .map(|m| {
+            let weight = movie_weight(data, profile, channel, m);
+            (m, rng.gen::<f64>().powf(1.0 / weight))
+        })
+        .collect();
+    movies.sort_by(|a, b| b.1.total_cmp(&a.1));
+    movies.into_iter().map(|(m, _)| m).collect()
+}
+
+/// Picks up to `amount` distinct movies, honoring the user's flags.
+fn choose_movies<'a>(
+    data: &'a Data,
+    profile: &Profile,
+    channel: Option<&Channel>,
+    amount: usize,
+) -> Vec<&'a Movie> {
+    let mut rng = rand::thread_rng();
+    let weighted: Vec<(&Movie, f64)> = data
+        .movies
+        .iter()
+        .map(|m| (m, movie_weight(data, profile, channel, m)))
+        .filter(|(_, w)| *w > 0.0)
+        .collect();
+    let mut ret: Vec<&Movie> = weighted
+        .choose_multiple_weighted(&mut rng, amount, |(_, w)| *w)
+        .map(|chosen| chosen.map(|(m, _)| *m).collect())
+        .unwrap_or_default();
+    ret.shuffle(&mut rng);
+    ret
+}
+
 fn get_folders_in_folder<P: AsRef<Path>>(folder: P) -> Vec<PathBuf> {
     let mut folders = Vec::new();
     if let Ok(entries) = read_dir(folder) {
-        for entry in entries {
-            if let Ok(entry) = entry {
-                let path = entry.path();
-                if path.is_dir() {
-                    folders.push(path);
-                }
+        for entry in entries.flatten() {
+            let path = entry.path();
+            if path.is_dir() {
+                folders.push(path);
             }
         }
     }
@@ -141,12 +427,10 @@ fn try_files(root_dir: &String, ls: Vec<String>) -> Vec<PathBuf> {
     let mut ret = Vec::new();
     for l in ls {
         if let Ok(entries) = glob(&l) {
-            for entry in entries {
-                if let Ok(path) = entry {
-                    if path.exists() {
-                        if let Some(r) = striped(root_dir, path) {
-                            ret.push(r);
-                        }
+            for path in entries.flatten() {
+                if path.exists() {
+                    if let Some(r) = striped(root_dir, path) {
+                        ret.push(r);
                     }
                 }
             }
@@ -170,142 +454,353 @@ fn remove_extension(path: &Path) -> String {
     }
 }
 
-fn load_movie_data(root_dir: &String) -> Vec<Movie> {
+/// How a video file relates to the movie `name` it was found for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Variant {
+    Main,
+    /// Kodi stacking, `-cd1`, `-part2`, `-disc1`, ...
+    Part(u32),
+    /// `Movie - 1080p`, `Movie-extended`, ...
+    Version,
+}
+
+const STACK_PREFIXES: [&str; 6] = ["cd", "dvd", "part", "pt", "disc", "disk"];
+const VERSION_WORDS: [&str; 12] = [
+    "4k",
+    "uhd",
+    "hdr",
+    "extended",
+    "unrated",
+    "uncut",
+    "remastered",
+    "theatrical",
+    "directors",
+    "director's",
+    "special",
+    "imax",
+];
+
+/// `None` if the file is a different movie that merely starts with `name`.
+fn variant(name: &str, stem: &str) -> Option<Variant> {
+    let rest = stem.strip_prefix(name)?;
+    if rest.is_empty() {
+        return Some(Variant::Main);
+    }
+    let tag = rest.trim_start_matches([' ', '-', '.', '_']).to_lowercase();
+    for prefix in STACK_PREFIXES {
+        if let Some(n) = tag.strip_prefix(prefix) {
+            if let Ok(n) = n.trim_start_matches([' ', '-', '.', '_']).parse() {
+                return Some(Variant::Part(n));
+            }
+        }
+    }
+    let word = tag.split([' ', '-', '.', '_']).next().unwrap_or_default();
+    let resolution = word
+        .strip_suffix('p')
+        .is_some_and(|r| (3..=4).contains(&r.len()) && r.chars().all(|c| c.is_ascii_digit()));
+    // Jellyfin names versions `Movie - <label>`
+    if rest.starts_with(" - ") || resolution || VERSION_WORDS.contains(&word) {
+        Some(Variant::Version)
+    } else {
+        None
+    }
+}
+
+/// Files of one movie, keyed by the path without extension and version suffix.
+#[derive(Debug, Default)]
+struct MovieFiles {
+    main: Vec<PathBuf>,
+    parts: BTreeMap<u32, PathBuf>,
+    versions: Vec<PathBuf>,
+}
+
+fn load_movie(
+    root_dir: &String,
+    naming: &dyn NamingConvention,
+    base: &str,
+    files: MovieFiles,
+) -> Option<Movie> {
+    let mut main = files.main.into_iter();
+    let mut parts = files.parts.into_values();
+    let mut versions: Vec<Version> = files
+        .versions
+        .into_iter()
+        .map(|path| Version {
+            path,
+            parts: Vec::new(),
+        })
+        .collect();
+    let path = match main.next() {
+        Some(path) => {
+            versions.extend(main.map(|path| Version {
+                path,
+                parts: Vec::new(),
+            }));
+            // a single file next to the stack, the whole stack is another version
+            if let Some(first) = parts.next() {
+                versions.push(Version {
+                    path: first,
+                    parts: parts.by_ref().collect(),
+                });
+            }
+            path
+        }
+        None => match parts.next() {
+            Some(path) => path,
+            None if !versions.is_empty() => versions.remove(0).path,
+            None => return None,
+        },
+    };
+    let movie = striped(root_dir, path.clone())?;
+    let size = path.metadata().ok().map(|m| m.len());
+    let art = |kind: &str| try_files(root_dir, naming.artwork(base, kind));
+    let poster = art("poster");
+    let thumb = art("thumb");
+    let escaped = glob::Pattern::escape(base);
+    let trailer = try_files(
+        root_dir,
+        vec![
+            escaped.clone() + "-trailer*.mp4",
+            escaped + "-trailer*.webm",
+        ],
+    );
+    let fanarts = art("fanart");
+    let subtitles = find_subtitles(root_dir, &path);
+    let extras = find_extras(root_dir, &path);
+    let info = naming.info(base, &path);
+
+    Some(Movie {
+        movie,
+        size,
+        parts: parts.filter_map(|p| striped(root_dir, p)).collect(),
+        versions: versions
+            .into_iter()
+            .filter_map(|v| {
+                Some(Version {
+                    path: striped(root_dir, v.path)?,
+                    parts: v
+                        .parts
+                        .into_iter()
+                        .filter_map(|p| striped(root_dir, p))
+                        .collect(),
+                })
+            })
+            .collect(),
+        thumb,
+        poster,
+        trailer,
+        fanarts,
+        clearlogo: art("clearlogo"),
+        clearart: art("clearart"),
+        banner: art("banner"),
+        landscape: art("landscape"),
+        disc: art("disc"),
+        subtitles,
+        extras,
+        info,
+    })
+}
+
+/// Containers browsers can't play, only reported when skipped.
+const UNSUPPORTED_CONTAINERS: [&str; 6] = ["mkv", "avi", "m4v", "mov", "wmv", "ts"];
+
+fn load_movie_data(root_dir: &String, naming: Naming) -> Vec<Movie> {
+    let start = std::time::Instant::now();
     let mut movies: Vec<Movie> = Vec::new();
 
     let folders: Vec<PathBuf> = get_folders_in_folder(root_dir);
+    tracing::info!(directory = %root_dir, folders = folders.len(), "scanning library");
 
     for f in folders {
+        let found = movies.len();
         // Get the last directory component
+        let naming = naming.for_folder(&f);
         if let Some(name) = f.file_name() {
-            if let Some(_dir) = striped(root_dir, f.clone()) {
-                // mkv and avi do not work currently in ff/chrome
-                // match all mp4, webm files in the folder usign glob and loop them
-                for ext in ["mp4", "webm"].iter() {
-                    // Movies
-                    for gl in [
-                        &format!("{}/{}.{}", f.display(), name.to_string_lossy(), ext).to_string(),
-                        &format!("{}/**/{}*.{}", f.display(), name.to_string_lossy(), ext)
-                            .to_string(),
-                    ] {
-                        match glob(gl) {
-                            Ok(entries) => {
-                                for entry in entries {
-                                    if let Ok(path) = entry {
-                                        // test that it does not end in -trailer
-                                        if path.display().to_string().contains("-trailer") == false
-                                        {
-                                            if let Some(movie) = striped(root_dir, path.clone()) {
-                                                let poster = try_files(
-                                                    root_dir,
-                                                    vec![
-                                                        remove_extension(&path) + &"-poster*.jpg",
-                                                        remove_extension(&path) + &"-poster*.png",
-                                                        //format!( "{}/{}{}",f.to_string_lossy(), name.to_string_lossy(), "-poster.jpg"),
-                                                        //format!( "{}/{}{}",f.to_string_lossy(), name.to_string_lossy(), "-poster.png"),
-                                                        //format!( "{}/{}",  f.to_string_lossy(),"poster.jpg"),
-                                                        //format!( "{}/{}",  f.to_string_lossy(),"poster.png"),
-                                                    ],
-                                                );
-                                                let thumb = try_files(
-                                                    root_dir,
-                                                    vec![
-                                                        remove_extension(&path) + &"-thumb*.jpg",
-                                                        remove_extension(&path) + &"-thumb*.png",
-                                                        //format!( "{}/{}{}",f.to_string_lossy(), name.to_string_lossy(), "-thumb.jpg"),
-                                                        //format!( "{}/{}{}", f.to_string_lossy(),name.to_string_lossy(), "-thumb.png"),
-                                                        //format!( "{}/{}", f.to_string_lossy(), "thumb.jpg"),
-                                                        //format!( "{}/{}", f.to_string_lossy(), "thumb.png"),
-                                                    ],
-                                                );
-
-                                                let trailer = try_files(
-                                                    root_dir,
-                                                    vec![
-                                                        remove_extension(&path) + &"-trailer*.mp4",
-                                                        remove_extension(&path) + &"-trailer*.webm",
-                                                        //format!( "{}/{}{}", f.to_string_lossy(),name.to_string_lossy(), "-trailer.mp4"),
-                                                        //format!( "{}/{}{}",f.to_string_lossy(), name.to_string_lossy(), "-trailer.webm"),
-                                                        //format!( "{}/{}", f.to_string_lossy(), "trailer.mp4"),
-                                                        //format!( "{}/{}", f.to_string_lossy(), "trailer.webm"),
-                                                    ],
-                                                );
-
-                                                let fanarts = try_files(
-                                                    root_dir,
-                                                    vec![
-                                                        remove_extension(&path) + &"-fanart*.jpg",
-                                                        remove_extension(&path) + &"-fanart*.png",
-                                                        //format!( "{}/{}{}", f.to_string_lossy(),name.to_string_lossy(), "-trailer.mp4"),
-                                                        //format!( "{}/{}{}",f.to_string_lossy(), name.to_string_lossy(), "-trailer.webm"),
-                                                        //format!( "{}/{}", f.to_string_lossy(), "trailer.mp4"),
-                                                        //format!( "{}/{}", f.to_string_lossy(), "trailer.webm"),
-                                                    ],
-                                                );
-
-                                                movies.push(Movie {
-                                                    movie,
-                                                    thumb,
-                                                    poster,
-                                                    trailer,
-                                                    fanarts,
-                                                });
-                                            }
-                                        }
-                                    }
+            let name = naming.movie_name(&name.to_string_lossy());
+            let folder = glob::Pattern::escape(&f.to_string_lossy());
+            let escaped = glob::Pattern::escape(&name);
+            // the globs overlap, a set keeps every file once
+            let mut paths = BTreeSet::new();
+            // mkv and avi do not work currently in ff/chrome
+            // match all mp4, webm files in the folder usign glob and loop them
+            for ext in ["mp4", "webm"].iter() {
+                // Movies
+                for gl in [
+                    format!("{}/{}.{}", folder, escaped, ext),
+                    format!("{}/**/{}*.{}", folder, escaped, ext),
+                ] {
+                    match glob(&gl) {
+                        Ok(entries) => {
+                            for path in entries.flatten() {
+                                // test that it does not end in -trailer
+                                if path.display().to_string().contains("-trailer")
+                                    || in_extras_folder(path.strip_prefix(&f).unwrap_or(&path))
+                                    || path.file_stem().is_some_and(|s| {
+                                        ExtraKind::from_suffix(&name, &s.to_string_lossy())
+                                            .is_some()
+                                    })
+                                {
+                                    continue;
                                 }
+                                paths.insert(path);
                             }
-                            Err(e) => eprintln!("Error: {}", e),
+                        }
+                        Err(e) => tracing::warn!(pattern = %gl, error = %e, "invalid glob"),
+                    }
+                }
+            }
+
+            let mut library: BTreeMap<String, MovieFiles> = BTreeMap::new();
+            for path in paths {
+                let stem = path
+                    .file_stem()
+                    .map(|s| naming.movie_name(&s.to_string_lossy()))
+                    .unwrap_or_default();
+                let (base, variant) = match variant(&name, &stem) {
+                    Some(variant) => {
+                        let parent = path.parent().unwrap_or(&f);
+                        (parent.join(&name).to_string_lossy().to_string(), variant)
+                    }
+                    None => (remove_extension(&path), Variant::Main),
+                };
+                let files = library.entry(base).or_default();
+                match variant {
+                    Variant::Main => files.main.push(path),
+                    Variant::Part(n) => {
+                        if let Some(other) = files.parts.insert(n, path) {
+                            files.versions.push(other);
                         }
                     }
+                    Variant::Version => files.versions.push(path),
+                }
+            }
+            for (base, files) in library {
+                if let Some(movie) = load_movie(root_dir, naming, &base, files) {
+                    tracing::debug!(
+                        movie = %movie.movie.display(),
+                        parts = movie.parts.len(),
+                        versions = movie.versions.len(),
+                        trailers = movie.trailer.len(),
+                        posters = movie.poster.len(),
+                        thumbs = movie.thumb.len(),
+                        fanarts = movie.fanarts.len(),
+                        subtitles = movie.subtitles.len(),
+                        "found movie"
+                    );
+                    movies.push(movie);
+                }
+            }
+        }
+        if movies.len() == found {
+            tracing::debug!(folder = %f.display(), "no mp4 or webm named like the folder");
+            for ext in UNSUPPORTED_CONTAINERS {
+                for path in try_files(root_dir, vec![format!("{}/**/*.{}", f.display(), ext)]) {
+                    tracing::debug!(file = %path.display(), "skipping unsupported container");
                 }
             }
         }
     }
 
+    tracing::info!(
+        movies = movies.len(),
+        duration_ms = start.elapsed().as_millis() as u64,
+        "scanned library"
+    );
     movies
 }
 
-async fn grid(data: web::Data<Arc<Data>>) -> impl Responder {
+fn grid_tile(data: &Data, profile: &Profile, m: &Movie) -> String {
     let mut rng = rand::thread_rng();
+    let details = details_url(&m.movie);
+    // only shown in kiosk mode
+    let caption = format!(
+        r#"<span class="caption">{}</span>"#,
+        escape_html(&m.title_with_year())
+    );
+    let poster = m
+        .poster
+        .choose(&mut rng)
+        .map(|p| {
+            format!(
+                r#" poster="{}""#,
+                escape_html(&media_url(data, profile, "image", p))
+            )
+        })
+        .unwrap_or_default();
+    match get_random_path(&data.config(), m) {
+        Some((path, PathType::Fanart)) | Some((path, PathType::Landscape)) => {
+            // the title on top of the backdrop
+            let logo = clearlogo_url(data, profile, m)
+                .map(|l| format!(r#"<img class="logo" src="{}">"#, escape_html(&l)))
+                .unwrap_or_default();
+            format!(
+                r#"<div class="brick"><a href="{}"><img src="{}" style="display:block;float:left;"></img>{}{}</a></div>"#,
+                escape_html(&details),
+                escape_html(&media_url(data, profile, "image", &path)),
+                logo,
+                caption
+            )
+        }
+        Some((path, PathType::Poster))
+        | Some((path, PathType::Thumb))
+        | Some((path, PathType::Clearlogo))
+        | Some((path, PathType::Clearart))
+        | Some((path, PathType::Banner))
+        | Some((path, PathType::Disc)) => {
+            // jpg png
+            format!(
+                r#"<div class="brick"><a href="{}"><img src="{}" style="display:block;float:left;"></img>{}</a></div>"#,
+                escape_html(&details),
+                escape_html(&media_url(data, profile, "image", &path)),
+                caption
+            )
+        }
+        Some((path, PathType::Trailer)) | Some((path, PathType::Extra)) => format!(
+            r#"<div class="brick"><a href="{}"><video autoplay muted loop{}> <source src="{}" type="video/mp4"> Your browser does not support the video tag.  </video>{}</a></div>"#,
+            escape_html(&details),
+            poster,
+            escape_html(&media_url(data, profile, "movie", &path)),
+            caption
+        ),
+        Some((_path, PathType::Video)) => format!(
+            r#"<div class="brick"><a href="{}"><video muted preload=metadata{}> <source src="{}" type="video/mp4"> Your browser does not support the video tag.  </video>{}</a></div>"#,
+            escape_html(&details),
+            poster,
+            escape_html(&media_url(data, profile, "movie", &m.movie)),
+            caption
+        ),
+        _ => "".to_string(),
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct GridQuery {
+    channel: Option<String>,
+    /// Swap tiles pushed by the server instead of loading more on scroll.
+    #[serde(default)]
+    ambient: bool,
+}
+
+async fn grid(
+    data: web::Data<Arc<Data>>,
+    profile: Profile,
+    query: web::Query<GridQuery>,
+    kiosk: web::Query<KioskQuery>,
+) -> impl Responder {
     //let image_data = data.lock().unwrap();
 
     //let image_data = load_image_data(root_dir, &extensions);
     //let random = image_data.choose_multiple(&mut rng, 100);
     //let movies = load_movie_data(&data.config.directory);
-    let movies = &data.movies;
-    let random = movies.choose_multiple(&mut rng, 50);
+    let random = choose_movies(
+        &data,
+        &profile,
+        find_channel(&data, &query.channel).as_ref(),
+        50,
+    )
+    .into_iter();
 
-    let image_tags: Vec<String> = random
-        .map(|m| {
-                match get_random_path(&data.config,m) {
-                Some((path, PathType::Poster)) | Some((path, PathType::Fanart)) | Some((path , PathType::Thumb)) => {
-                // jpg png
-                format!(
-                        r#"<div class="brick"><a href="/movie/{}"><img src="/image/{}" style="display:block;float:left;"></img></a></div>"#,
-                        m.movie.display(), path.display()
-                       )
-                },
-                Some((path, PathType::Trailer)) => {
-                if let Some(poster) = &m.poster.choose(&mut rng) {
-                format!(r#"<a href="/movie/{}"><video autoplay muted loop poster="/image/{}"> <source src="/movie/{}" type="video/mp4"> Your browser does not support the video tag.  </video></a>"#,m.movie.display(),poster.display(),path.display())
-                }
-                else {
-                format!(r#"<a href="/movie/{}"><video autoplay muted loop> <source src="/movie/{}" type="video/mp4"> Your browser does not support the video tag.  </video></a>"#,m.movie.display(),path.display())
-                }
-                },
-                Some((_path, PathType::Video)) => {
-                if let Some(poster) = &m.poster.choose(&mut rng) {
-                format!(r#"<a href="/movie/{}"><video muted preload=metadata poster="/image/{}"> <source src="/movie/{}" type="video/mp4"> Your browser does not support the video tag.  </video></a>"#,m.movie.display(),poster.display(),m.movie.display())
-                }
-                else{
-                    format!(r#"<a href="/movie/{}"><video muted preload=metadata> <source src="/movie/{}" type="video/mp4"> Your browser does not support the video tag.  </video></a>"#,m.movie.display(),m.movie.display())
-                }
-                },
-                _ => {"".to_string()}
-                }
-        })
-    .collect();
+    let image_tags: Vec<String> = random.map(|m| grid_tile(&data, &profile, m)).collect();
 
     let html_content = format!(
         r#"<!DOCTYPE html>
@@ -328,9 +823,19 @@ padding: 0;
 gap: 0;
 }}
 .brick {{
+position: relative;
 margin: 0;
 padding: 0;
 }}
+.brick .logo {{
+position: absolute;
+left: 5%;
+bottom: 5%;
+width: 40%;
+height: 10vh;
+object-fit: contain;
+object-position: left bottom;
+}}
 img {{
 width: 100%;
 height: 33vh;
@@ -342,27 +847,55 @@ height: 33vh;
         object-fit: cover;
 display: block;
 }}
+body.ambient {{
+overflow: hidden;
+}}
+{kiosk_style}
 </style>
 
 </head>
-<body>
+<body class="{kiosk_class}">
 <div id="therow" class="row">
 {}
 </div>
 <script>
+{kiosk_script}
+startKiosk({started});
+const ambient = {ambient};
+function seekRandom(video) {{
+        video.addEventListener('loadedmetadata', function() {{
+                var randomTime = Math.random() * video.duration;
+                video.currentTime = randomTime;
+                }});
+}}
 window.addEventListener('DOMContentLoaded', function() {{
         var videos = document.querySelectorAll('video');
-        videos.forEach(function(video) {{
-                video.addEventListener('loadedmetadata', function() {{
-                        var randomTime = Math.random() * video.duration;
-                        video.currentTime = randomTime;
-                        }});
-                }});
+        videos.forEach(seekRandom);
         }});
+if (ambient) {{
+        document.body.classList.add('ambient');
+        // the server pushes a new tile now and then, it replaces a random one
+        const events = new EventSource('/grid/events' + window.location.search);
+        events.addEventListener('tile', function(event) {{
+                const row = document.getElementById("therow");
+                const template = document.createElement('template');
+                template.innerHTML = JSON.parse(event.data).html;
+                const tile = template.content.firstElementChild;
+                if (!tile || row.children.length == 0) {{
+                        return;
+                }}
+                const old = row.children[Math.floor(Math.random() * row.children.length)];
+                tile.querySelectorAll('video').forEach(seekRandom);
+                old.replaceWith(tile);
+                }});
+}}
 document.addEventListener('DOMContentLoaded', function() {{
         window.addEventListener('scroll', function() {{
+                if (ambient) {{
+                return;
+                }}
                 if ((window.innerHeight *2 + window.scrollY) >= document.body.offsetHeight) {{
-                fetch('/grid')
+                fetch(window.location.href)
                 .then(response => response.text())
                 .then(data => {{
                         const parser = new DOMParser();
@@ -380,7 +913,12 @@ document.addEventListener('DOMContentLoaded', function() {{
 </script>
 </body>
 </html>"#,
-        image_tags.join("\n")
+        image_tags.join("\n"),
+        ambient = query.ambient,
+        kiosk_style = kiosk::KIOSK_STYLE,
+        kiosk_class = kiosk.class(),
+        kiosk_script = kiosk::KIOSK_SCRIPT,
+        started = data.started,
     );
 
     HttpResponse::Ok()
@@ -388,21 +926,14 @@ document.addEventListener('DOMContentLoaded', function() {{
         .body(html_content)
 }
 
-async fn serve_image(data: web::Data<Arc<Data>>, path: web::Path<String>) -> impl Responder {
-    let root_dir = &data.config.directory;
-    let p = root_dir.to_owned() + &path.into_inner();
-    let path = PathBuf::from(p);
-    match is_within_folder(&PathBuf::from(root_dir), &path) {
-        Ok(is_within) => {
-            if is_within {
-                NamedFile::open(path)
-                    .map_err(|_| actix_web::error::ErrorNotFound("Image not found"))
-            } else {
-                Err(actix_web::error::ErrorNotFound("Image not found"))
-            }
-        }
-        Err(e) => Err(actix_web::error::ErrorNotFound(e)),
-    }
+async fn serve_image(
+    req: HttpRequest,
+    data: web::Data<Arc<Data>>,
+    profile: Profile,
+    path: web::Path<String>,
+) -> Result<HttpResponse> {
+    let file = library_file(&data, &profile, FileKind::Image, &path.into_inner())?;
+    Ok(file.into_response(&req).await)
 }
 
 fn ensure_trailing_slash(path_str: String) -> String {
@@ -417,36 +948,455 @@ fn ensure_trailing_slash(path_str: String) -> String {
     }
 }
 
-fn is_within_folder(folder: &PathBuf, path: &PathBuf) -> Result<bool, String> {
-    // append / if missing from folder
+async fn serve_movie(
+    req: HttpRequest,
+    data: web::Data<Arc<Data>>,
+    profile: Profile,
+    path: web::Path<String>,
+) -> Result<HttpResponse> {
+    let file = library_file(&data, &profile, FileKind::Video, &path.into_inner())?;
+    let metrics = data.metrics.clone();
+    let mut res = file.into_response(&req).await;
+    dlna::add_stream_headers(&req, &mut res);
+    Ok(res.map_body(|_, body| BoxBody::new(StreamBody::new(body, metrics))))
+}
 
-    let folder = folder.canonicalize().map_err(|e| e.to_string())?;
-    let path = path.canonicalize().map_err(|e| e.to_string())?;
+async fn serve_subtitle(
+    data: web::Data<Arc<Data>>,
+    profile: Profile,
+    path: web::Path<String>,
+) -> Result<HttpResponse> {
+    let path = path.into_inner();
+    let file = library_file(&data, &profile, FileKind::Subtitle, &path)?;
+    let content = file
+        .into_bytes()
+        .await
+        .map_err(|_| actix_web::error::ErrorNotFound("Not found"))?;
+    Ok(HttpResponse::Ok()
+        .content_type("text/vtt; charset=utf-8")
+        .body(to_webvtt(
+            Path::new(&path),
+            &String::from_utf8_lossy(&content),
+        )))
+}
 
-    Ok(path.starts_with(&folder))
+fn escape_html(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&#39;")
 }
 
-async fn serve_movie(data: web::Data<Arc<Data>>, path: web::Path<String>) -> impl Responder {
-    let root_dir = &data.config.directory;
-    let p = root_dir.to_owned() + &path.into_inner();
-    let file_path = PathBuf::from(p);
-    match is_within_folder(&PathBuf::from(root_dir), &file_path) {
-        Ok(is_within) => {
-            if is_within {
-                NamedFile::open(&file_path)
-                    .map_err(|_| actix_web::error::ErrorNotFound("video not found"))
-            } else {
-                Err(actix_web::error::ErrorNotFound("Not within folder"))
-            }
+/// Characters escaped in the path of a url.
+const PATH_ESCAPES: &AsciiSet = &CONTROLS
+    .add(b' ')
+    .add(b'"')
+    .add(b'#')
+    .add(b'%')
+    .add(b'<')
+    .add(b'>')
+    .add(b'?')
+    .add(b'`')
+    .add(b'{')
+    .add(b'}');
+
+/// `scheme://host` as the client reached the server, for urls used outside the browser.
+fn base_url(req: &HttpRequest) -> String {
+    let info = req.connection_info();
+    format!("{}://{}", info.scheme(), info.host())
+}
+
+/// Url of a library file below `/movie`, `/image` or `/subtitle`, signed when access is restricted.
+fn media_url(data: &Data, profile: &Profile, route: &str, path: &Path) -> String {
+    let url = format!("/{}/{}", route, path.display());
+    let encoded = utf8_percent_encode(&url, PATH_ESCAPES).to_string();
+    match &data.signer {
+        Some(signer) => format!("{}?{}", encoded, signer.sign(&url, &profile.0)),
+        None => encoded,
+    }
+}
+
+/// Url of the movie page.
+fn details_url(path: &Path) -> String {
+    utf8_percent_encode(&format!("/details/{}", path.display()), PATH_ESCAPES).to_string()
+}
+
+/// A random clearlogo of the movie for overlays.
+fn clearlogo_url(data: &Data, profile: &Profile, movie: &Movie) -> Option<String> {
+    movie
+        .clearlogo
+        .choose(&mut rand::thread_rng())
+        .map(|l| media_url(data, profile, "image", l))
+}
+
+fn subtitle_tracks(data: &Data, profile: &Profile, movie: &Movie) -> Vec<serde_json::Value> {
+    movie
+        .subtitles
+        .iter()
+        .map(|s| {
+            json!({
+                "src": media_url(data, profile, "subtitle", &s.path),
+                "srclang": s.language,
+                "label": s.label,
+            })
+        })
+        .collect()
+}
+
+fn track_tags(data: &Data, profile: &Profile, movie: &Movie) -> String {
+    movie
+        .subtitles
+        .iter()
+        .map(|s| {
+            format!(
+                r#"<track kind="subtitles" src="{}" label="{}"{}>"#,
+                escape_html(&media_url(data, profile, "subtitle", &s.path)),
+                escape_html(&s.label),
+                s.language
+                    .as_ref()
+                    .map(|l| format!(r#" srclang="{}""#, escape_html(l)))
+                    .unwrap_or_default()
+            )
+        })
+        .collect::<Vec<String>>()
+        .join("\n")
+}
+
+async fn details(
+    data: web::Data<Arc<Data>>,
+    profile: Profile,
+    path: web::Path<String>,
+) -> impl Responder {
+    let path = PathBuf::from(path.into_inner());
+    let m = match data.movies.iter().find(|m| m.movie == path) {
+        Some(m) => m,
+        None => return HttpResponse::NotFound().body("movie not found"),
+    };
+    if !is_allowed(&data, &profile, None, m) {
+        return HttpResponse::Forbidden().body("restricted");
+    }
+    let mut rng = rand::thread_rng();
+    let flags = data.store.flags(&profile.0, &m.movie.to_string_lossy());
+
+    let image = |p: Option<&PathBuf>| p.map(|p| media_url(&data, &profile, "image", p));
+    let fanart = image(m.fanarts.choose(&mut rng));
+    let poster = image(m.poster.choose(&mut rng));
+    let still = fanart.clone().or_else(|| image(m.thumb.choose(&mut rng)));
+
+    let title = m.title_with_year();
+    let heading = match image(m.clearlogo.choose(&mut rng)) {
+        Some(logo) => format!(
+            r#"<img class="logo" src="{}" alt="{}" title="{1}">"#,
+            escape_html(&logo),
+            escape_html(&title)
+        ),
+        None => escape_html(&title),
+    };
+    let mut facts = Vec::new();
+    if let Some(original_title) = &m.info.original_title {
+        if Some(original_title) != m.info.title.as_ref() {
+            facts.push(escape_html(original_title));
+        }
+    }
+    if let Some(certification) = &m.info.certification {
+        facts.push(escape_html(certification));
+    }
+    if let Some(runtime) = &m.info.runtime {
+        facts.push(format!("{} min", escape_html(runtime)));
+    }
+    if !m.info.genres.is_empty() {
+        facts.push(escape_html(&m.info.genres.join(", ")));
+    }
+    if !m.info.directors.is_empty() {
+        facts.push(escape_html(&m.info.directors.join(", ")));
+    }
+    if let Some(rating) = &m.info.rating {
+        facts.push(format!("&#9733; {}", escape_html(rating)));
+    }
+
+    let trailers: Vec<String> = m
+        .trailers()
+        .map(|t| {
+            format!(
+                r#"<video controls muted preload=metadata src="{}"></video>"#,
+                escape_html(&media_url(&data, &profile, "movie", t))
+            )
+        })
+        .collect();
+
+    let extras: Vec<String> = m
+        .extras
+        .iter()
+        .filter(|e| e.kind != ExtraKind::Trailer)
+        .map(|e| {
+            format!(
+                r#"<figure><video controls preload=metadata src="{}"></video><figcaption>{}: {}</figcaption></figure>"#,
+                escape_html(&media_url(&data, &profile, "movie", &e.path)),
+                e.kind.label(),
+                escape_html(&e.path.file_stem().unwrap_or_default().to_string_lossy())
+            )
+        })
+        .collect();
+
+    let part_urls = |parts: &[PathBuf]| {
+        let urls: Vec<String> = parts
+            .iter()
+            .map(|p| media_url(&data, &profile, "movie", p))
+            .collect();
+        serde_json::to_string(&urls).unwrap_or_default()
+    };
+    let versions = if m.versions.is_empty() {
+        String::new()
+    } else {
+        let main = Version {
+            path: m.movie.clone(),
+            parts: m.parts.clone(),
+        };
+        let buttons: Vec<String> = std::iter::once(&main)
+            .chain(&m.versions)
+            .enumerate()
+            .map(|(i, v)| {
+                format!(
+                    r#"<button class="version{}" data-src="{}" data-parts="{}">{}</button>"#,
+                    if i == 0 { " active" } else { "" },
+                    escape_html(&media_url(&data, &profile, "movie", &v.path)),
+                    escape_html(&part_urls(&v.parts)),
+                    escape_html(&v.path.file_stem().unwrap_or_default().to_string_lossy())
+                )
+            })
+            .collect();
+        format!("<p>\n{}\n</p>", buttons.join("\n"))
+    };
+
+    let html_content = format!(
+        r#"<!DOCTYPE html>
+            <html lang="en">
+            <head>
+            <meta charset="UTF-8">
+            <meta name="viewport" content="width=device-width, initial-scale=1.0">
+            <title>{title}</title>
+            <style>
+            body {{
+margin: 0;
+min-height: 100vh;
+color: white;
+font-family: sans-serif;
+background-color: black;
+background-image: linear-gradient(rgba(0, 0, 0, 0.6), rgba(0, 0, 0, 0.9)){fanart};
+background-size: cover;
+background-position: center;
+background-attachment: fixed;
+}}
+.header {{
+display: flex;
+gap: 2em;
+padding: 2em;
+}}
+.logo {{
+max-width: 40vw;
+max-height: 15vh;
+}}
+.poster {{
+height: 40vh;
+box-shadow: 0 0 1em black;
+}}
+.player {{
+width: 100%;
+max-height: 90vh;
+background-color: black;
+}}
+.trailers {{
+display: flex;
+flex-wrap: wrap;
+gap: 1em;
+padding: 2em;
+}}
+.trailers video {{
+height: 25vh;
+}}
+.trailers figure {{
+margin: 0;
+}}
+.flag, .version {{
+color: white;
+background-color: transparent;
+border: 1px solid white;
+border-radius: 1em;
+padding: 0.3em 1em;
+cursor: pointer;
+}}
+.flag.active, .version.active {{
+color: black;
+background-color: white;
+}}
+</style>
+</head>
+<body>
+<div class="header">
+{poster}
+<div>
+<h1>{heading}</h1>
+<h3>{tagline}</h3>
+<p>{facts}</p>
+<p>{plot}</p>
+<p>
+<button id="favorite" class="flag{favorite}" title="Show more often">&#9733; Favorite</button>
+<button id="disliked" class="flag{disliked}" title="Show less often">&#128078; Dislike</button>
+<button id="hidden" class="flag{hidden}" title="Never show in grid and tv">Hide</button>
+</p>
+{versions}
+</div>
+</div>
+<video id="player" class="player" controls preload=metadata{still} src="{src}" data-movie="{movie}">
+{tracks}
+    Your browser does not support the video tag.
+</video>
+<div class="trailers">
+{trailers}
+{extras}
+</div>
+<script>
+{tracking}
+const player = document.getElementById('player');
+trackProgress(player);
+player.addEventListener('loadedmetadata', function() {{
+        player.currentTime = {resume};
+        }}, {{ once: true }});
+// stacked movies continue with the next part
+let parts = {parts};
+player.addEventListener('ended', function() {{
+        if (parts.length > 0) {{
+                player.src = parts.shift();
+                player.play();
+                }}
+        }});
+document.querySelectorAll('.version').forEach(function(button) {{
+        button.addEventListener('click', function() {{
+                const position = player.currentTime;
+                player.src = button.dataset.src;
+                parts = JSON.parse(button.dataset.parts);
+                player.addEventListener('loadedmetadata', function() {{
+                        player.currentTime = position;
+                        player.play();
+                        }}, {{ once: true }});
+                document.querySelectorAll('.version').forEach(function(b) {{
+                        b.classList.toggle('active', b === button);
+                        }});
+                }});
+        }});
+document.querySelectorAll('.flag').forEach(function(button) {{
+        button.addEventListener('click', function() {{
+                const update = {{}};
+                update[button.id] = !button.classList.contains('active');
+                fetch('/api/flags/' + player.dataset.movie.split('/').map(encodeURIComponent).join('/'), {{
+                        method: 'POST',
+                        headers: {{ 'Content-Type': 'application/json' }},
+                        body: JSON.stringify(update)
+                        }})
+                .then(response => response.json())
+                .then(flags => {{
+                        document.querySelectorAll('.flag').forEach(function(b) {{
+                                b.classList.toggle('active', flags[b.id]);
+                                }});
+                        }})
+                .catch(error => {{
+                        console.error('Error updating flags:', error);
+                        }});
+                }});
+        }});
+</script>
+</body>
+</html>"#,
+        tracking = TRACKING_SCRIPT,
+        favorite = if flags.favorite { " active" } else { "" },
+        disliked = if flags.disliked { " active" } else { "" },
+        hidden = if flags.hidden { " active" } else { "" },
+        resume = data
+            .store
+            .progress(&profile.0, &m.movie.to_string_lossy())
+            .and_then(|p| p.resume_position())
+            .unwrap_or(0.0),
+        title = escape_html(&title),
+        heading = heading,
+        fanart = fanart
+            .as_ref()
+            .map(|f| format!(r#", url("{}")"#, f))
+            .unwrap_or_default(),
+        poster = poster
+            .map(|p| format!(r#"<img class="poster" src="{}">"#, escape_html(&p)))
+            .unwrap_or_default(),
+        tagline = escape_html(m.info.tagline.as_deref().unwrap_or_default()),
+        facts = facts.join(" &middot; "),
+        plot = escape_html(m.info.plot.as_deref().unwrap_or_default()),
+        still = still
+            .map(|s| format!(r#" poster="{}""#, escape_html(&s)))
+            .unwrap_or_default(),
+        src = escape_html(&media_url(&data, &profile, "movie", &m.movie)),
+        movie = escape_html(&m.movie.display().to_string()),
+        tracks = track_tags(&data, &profile, m),
+        trailers = trailers.join("\n"),
+        extras = extras.join("\n"),
+        versions = versions,
+        parts = part_urls(&m.parts).replace("</", "<\\/"),
+    );
+    HttpResponse::Ok()
+        .content_type("text/html")
+        .body(html_content)
+}
+
+/// Reports playback of `video.dataset.movie` to `/api/played` and `/api/progress`.
+const TRACKING_SCRIPT: &str = r#"
+function trackProgress(video) {
+    let lastReport = 0;
+    let played = null;
+    function post(url, body) {
+        fetch(url, {
+            method: 'POST',
+            headers: { 'Content-Type': 'application/json' },
+            body: JSON.stringify(body)
+        }).catch(function(error) {
+            console.error('Error reporting progress:', error);
+        });
+    }
+    function report() {
+        if (!video.dataset.movie || !video.duration) {
+            return;
         }
-        Err(e) => Err(actix_web::error::ErrorNotFound(e)),
+        lastReport = Date.now();
+        post('/api/progress', {
+            movie: video.dataset.movie,
+            position: video.currentTime,
+            duration: video.duration
+        });
     }
+    video.addEventListener('playing', function() {
+        if (video.dataset.movie && played !== video.dataset.movie) {
+            played = video.dataset.movie;
+            post('/api/played', { movie: video.dataset.movie });
+        }
+    });
+    video.addEventListener('timeupdate', function() {
+        if (Date.now() - lastReport > 10000) {
+            report();
+        }
+    });
+    video.addEventListener('pause', report);
+    video.addEventListener('ended', report);
 }
+"#;
 
-async fn tv(data: web::Data<Arc<Data>>) -> impl Responder {
+async fn tv(
+    data: web::Data<Arc<Data>>,
+    profile: Profile,
+    query: web::Query<ChannelQuery>,
+    kiosk: web::Query<KioskQuery>,
+) -> impl Responder {
+    let channel = find_channel(&data, &query.channel);
+    let channel = channel.as_ref();
     //let mut movies = load_movie_data(&data.config.directory);
     let mut rng = rand::thread_rng();
-    let mut movies = data.movies.clone();
+    let mut movies = tv_movies(&data, &profile, channel);
     movies.shuffle(&mut rng);
 
     let html_content = format!(
@@ -466,26 +1416,176 @@ height: 95%;
 object-fit: cover;
 transform: translate(-50%, -50%);
 }}
+body.kiosk {{
+margin: 0;
+background: black;
+overflow: hidden;
+}}
+body.kiosk #videoPlayer {{
+width: 100vw;
+height: 100vh;
+object-fit: contain;
+}}
+#logo {{
+position: fixed;
+right: 4vw;
+bottom: 8vh;
+width: 25vw;
+height: 12vh;
+object-fit: contain;
+object-position: right bottom;
+pointer-events: none;
+opacity: 0;
+transition: opacity 2s;
+}}
+#logo.show {{
+opacity: 0.9;
+}}
+{kiosk_style}
 </style>
 </head>
-<body>
-<video id="videoPlayer" controls autoplay muted>
+<body class="{kiosk_class}">
+<video id="videoPlayer"{controls} autoplay muted>
     <source type="video/mp4">
     Your browser does not support the video tag.
     </video>
+<div id="caption" class="caption"></div>
+<img id="logo" alt="">
 
     <script>
+    {}
+    {}
+    {kiosk_script}
+    startKiosk({started});
     document.addEventListener('DOMContentLoaded', function() {{
             const videoPlayer = document.getElementById('videoPlayer');
-            const videoSources = ["{}"];
+            const caption = document.getElementById('caption');
+            const logo = document.getElementById('logo');
+            let logoTimer = null;
+            const videoSources = {};
+            const channel = {};
+            const previous = [];
+            let current = null;
+
+            const totalWeight = videoSources.reduce(function(sum, video) {{
+                    return sum + video.weight;
+                    }}, 0);
+
+            function pickVideo() {{
+            let r = Math.random() * totalWeight;
+            for (const video of videoSources) {{
+            r -= video.weight;
+            if (r < 0) {{
+            return video;
+            }}
+            }}
+            return videoSources[videoSources.length - 1];
+            }}
 
             function playRandomVideo() {{
-            const randomIndex = Math.floor(Math.random() * videoSources.length);
-            videoPlayer.src = videoSources[randomIndex];
-            videoPlayer.play();
+            playVideo(pickVideo(), true);
             }}
 
+            function playVideo(video, remember) {{
+            if (remember && current) {{
+            previous.push(current);
+            }}
+            current = video;
+            videoPlayer.querySelectorAll('track').forEach(function(track) {{
+                    track.remove();
+                    }});
+            video.tracks.forEach(function(track) {{
+                    const t = document.createElement('track');
+                    t.kind = 'subtitles';
+                    t.src = track.src;
+                    t.label = track.label;
+                    if (track.srclang) {{
+                    t.srclang = track.srclang;
+                    }}
+                    videoPlayer.appendChild(t);
+                    }});
+            videoPlayer.src = video.src;
+            videoPlayer.dataset.movie = video.movie;
+            caption.textContent = video.title || '';
+            // the clearlogo for the first seconds of a movie
+            clearTimeout(logoTimer);
+            logo.classList.remove('show');
+            if (video.logo) {{
+            logo.onload = function() {{
+                    logo.classList.add('show');
+                    logoTimer = setTimeout(function() {{
+                            logo.classList.remove('show');
+                            }}, 10000);
+                    }};
+            logo.src = video.logo;
+            }} else {{
+            logo.removeAttribute('src');
+            }}
+            videoPlayer.play().catch(function() {{}});
+            }}
+
+            // skip videos that fail to load or stop loading, slower when nothing plays
+            let failures = 0;
+            let skipTimer = null;
+            function skipAfter(ms) {{
+            clearTimeout(skipTimer);
+            skipTimer = setTimeout(function() {{
+                    // not worth going back to
+                    current = null;
+                    playRandomVideo();
+                    }}, ms);
+            }}
+            videoPlayer.addEventListener('error', function() {{
+                    failures += 1;
+                    skipAfter(Math.min(1000 * 2 ** failures, 60000));
+                    }});
+            videoPlayer.addEventListener('stalled', function() {{
+                    if (!videoPlayer.paused && !videoPlayer.error) {{
+                    skipAfter(20000);
+                    }}
+                    }});
+            videoPlayer.addEventListener('playing', function() {{
+                    failures = 0;
+                    }});
+            ['timeupdate', 'pause'].forEach(function(event) {{
+                    videoPlayer.addEventListener(event, function() {{
+                            if (!videoPlayer.error) {{
+                            clearTimeout(skipTimer);
+                            }}
+                            }});
+                    }});
+
             videoPlayer.addEventListener('ended', playRandomVideo);
+            trackProgress(videoPlayer);
+            connectScreen(videoPlayer, channel, {{
+                next: playRandomVideo,
+                previous: function() {{
+                    if (previous.length) {{
+                        playVideo(previous.pop(), false);
+                    }} else {{
+                        videoPlayer.currentTime = 0;
+                    }}
+                }},
+                pause: function() {{
+                    if (videoPlayer.paused) {{
+                        videoPlayer.play();
+                    }} else {{
+                        videoPlayer.pause();
+                    }}
+                }},
+                set_channel: function(message) {{
+                    const params = new URLSearchParams(location.search);
+                    if (message.channel) {{
+                        params.set('channel', message.channel);
+                    }} else {{
+                        params.delete('channel');
+                    }}
+                    location.search = params.toString();
+                }},
+                play_movie: function(message) {{
+                    playVideo({{ src: message.src, movie: message.movie, title: message.title, logo: message.logo, tracks: message.tracks }}, true);
+                }}
+            }});
 
             // Play a random video when the page loads
             playRandomVideo();
@@ -493,12 +1593,31 @@ transform: translate(-50%, -50%);
 </script>
 </body>
 </html>"#,
-        movies
-            .iter()
-            .map(|m| "/movie/".to_owned()
-                + &m.movie.to_string_lossy().to_string().replace("\"", "\\\""))
-            .collect::<Vec<String>>()
-            .join("\",\"")
+        TRACKING_SCRIPT,
+        remote::SCREEN_SCRIPT,
+        serde_json::Value::from(
+            movies
+                .iter()
+                .map(|m| json!({
+                    "src": media_url(&data, &profile, "movie", &m.movie),
+                    "movie": m.movie.display().to_string(),
+                    "title": m.title_with_year(),
+                    "logo": clearlogo_url(&data, &profile, m),
+                    "weight": movie_weight(&data, &profile, channel, m),
+                    "tracks": subtitle_tracks(&data, &profile, m),
+                }))
+                .collect::<Vec<serde_json::Value>>()
+        )
+        .to_string()
+        .replace("</", "<\\/"),
+        serde_json::Value::from(channel.map(|c| c.name.clone()))
+            .to_string()
+            .replace("</", "<\\/"),
+        kiosk_style = kiosk::KIOSK_STYLE,
+        kiosk_class = kiosk.class(),
+        kiosk_script = kiosk::KIOSK_SCRIPT,
+        started = data.started,
+        controls = if kiosk.kiosk { "" } else { " controls" },
     );
 
     HttpResponse::Ok()
@@ -506,8 +1625,42 @@ transform: translate(-50%, -50%);
         .body(html_content)
 }
 
-async fn index() -> impl Responder {
-    HttpResponse::Ok().content_type("text/html").body(
+async fn index(data: web::Data<Arc<Data>>, profile: Profile) -> impl Responder {
+    let continue_watching: Vec<String> = data
+        .store
+        .continue_watching(&profile.0)
+        .iter()
+        .filter_map(|(movie, progress)| {
+            let m = data
+                .movies
+                .iter()
+                .find(|m| m.movie.to_string_lossy() == *movie)
+                .filter(|m| is_allowed(&data, &profile, None, m))?;
+            let image = m
+                .poster
+                .first()
+                .or(m.thumb.first())
+                .or(m.fanarts.first())
+                .map(|p| {
+                    format!(
+                        r#"<img src="{}">"#,
+                        escape_html(&media_url(&data, &profile, "image", p))
+                    )
+                })
+                .unwrap_or_else(|| escape_html(&m.title()));
+            Some(format!(
+                r#"<a href="{}" title="{}">{}<progress max="{}" value="{}"></progress></a>"#,
+                escape_html(&details_url(&m.movie)),
+                escape_html(&m.title()),
+                image,
+                progress.duration,
+                progress.position
+            ))
+        })
+        .take(10)
+        .collect();
+
+    let html_content = format!(
         r#"
                 <!DOCTYPE html>
                 <html lang="en">
@@ -515,15 +1668,142 @@ async fn index() -> impl Responder {
                 <meta charset="UTF-8">
                 <meta name="viewport" content="width=device-width, initial-scale=1.0">
                 <title>Random Video Server</title>
+                <style>
+                .continue {{
+display: flex;
+gap: 1em;
+overflow-x: auto;
+}}
+.continue a {{
+display: flex;
+flex-direction: column;
+}}
+.continue img {{
+height: 25vh;
+}}
+</style>
                 </head>
                 <body>
                 <div class="container">
                 <h1>Choose Your View</h1>
                 <a href="/grid" class="button">Grid</a>
                 <a href="/tv" class="button">TV</a>
+                <a href="/remote" class="button">Remote</a>
+                {}
+                {}
                 </div>
+                {}
                 </body>
                 </html>"#,
+        data.config()
+            .channels
+            .iter()
+            .map(|c| {
+                format!(
+                    r#"<p>{name}: <a href="/grid?channel={query}" class="button">Grid</a> <a href="/tv?channel={query}" class="button">TV</a></p>"#,
+                    name = escape_html(&c.name),
+                    query = utf8_percent_encode(&c.name, NON_ALPHANUMERIC)
+                )
+            })
+            .collect::<Vec<String>>()
+            .join("\n"),
+        if data.auth.is_some() {
+            format!(
+                r#"<form method="post" action="/logout"><p>Logged in as {} &middot; <button type="submit">Logout</button></p></form>"#,
+                escape_html(&profile.0)
+            )
+        } else {
+            "".to_string()
+        },
+        if continue_watching.is_empty() {
+            "".to_string()
+        } else {
+            format!(
+                r#"<h2>Continue Watching</h2><div class="continue">{}</div>"#,
+                continue_watching.join("\n")
+            )
+        }
+    );
+    HttpResponse::Ok()
+        .content_type("text/html")
+        .body(html_content)
+}
+
+#[derive(Debug, Deserialize)]
+struct ProgressReport {
+    movie: String,
+    position: f64,
+    duration: f64,
+}
+
+#[derive(Debug, Deserialize)]
+struct PlayedReport {
+    movie: String,
+}
+
+fn is_known_movie(data: &Data, movie: &str) -> bool {
+    data.movies
+        .iter()
+        .any(|m| m.movie.to_string_lossy() == movie)
+}
+
+async fn report_progress(
+    data: web::Data<Arc<Data>>,
+    profile: Profile,
+    report: web::Json<ProgressReport>,
+) -> impl Responder {
+    if !is_known_movie(&data, &report.movie) {
+        return HttpResponse::NotFound().body("movie not found");
+    }
+    data.store
+        .record_progress(&profile.0, &report.movie, report.position, report.duration);
+    HttpResponse::NoContent().finish()
+}
+
+async fn report_played(
+    data: web::Data<Arc<Data>>,
+    profile: Profile,
+    report: web::Json<PlayedReport>,
+) -> impl Responder {
+    if !is_known_movie(&data, &report.movie) {
+        return HttpResponse::NotFound().body("movie not found");
+    }
+    data.store.record_played(&profile.0, &report.movie);
+    HttpResponse::NoContent().finish()
+}
+
+async fn get_flags(
+    data: web::Data<Arc<Data>>,
+    profile: Profile,
+    path: web::Path<String>,
+) -> impl Responder {
+    let movie = path.into_inner();
+    if !is_known_movie(&data, &movie) {
+        return HttpResponse::NotFound().body("movie not found");
+    }
+    HttpResponse::Ok().json(data.store.flags(&profile.0, &movie))
+}
+
+async fn update_flags(
+    data: web::Data<Arc<Data>>,
+    profile: Profile,
+    path: web::Path<String>,
+    update: web::Json<FlagsUpdate>,
+) -> impl Responder {
+    let movie = path.into_inner();
+    if !is_known_movie(&data, &movie) {
+        return HttpResponse::NotFound().body("movie not found");
+    }
+    HttpResponse::Ok().json(data.store.update_flags(&profile.0, &movie, &update))
+}
+
+async fn history(data: web::Data<Arc<Data>>, profile: Profile) -> impl Responder {
+    HttpResponse::Ok().json(
+        data.store
+            .history(&profile.0)
+            .iter()
+            .map(|h| json!({"movie": h.movie, "time": h.time}))
+            .collect::<Vec<serde_json::Value>>(),
     )
 }
 
@@ -532,76 +1812,441 @@ async fn main() -> std::io::Result<()> {
     // Parse command line arguments
     let args = OptConfig::parse();
 
-    // Determine the config file path
-    let default_config_path = dirs::config_dir()
-        .map(|p| p.join("rp").join("config.toml"))
-        .expect("Could not determine default config directory");
-    let config_path = args.config.unwrap_or(default_config_path);
-
-    // Read the configuration file
-    let config_content = read_to_string(&config_path).unwrap_or(format!(""));
-    let file_config: OptConfig = toml::from_str(&config_content)
-        .map_err(|e| io::Error::new(io::ErrorKind::Other, e.to_string()))?;
-
-    match args.directory.or(file_config.directory) {
-        Some(directory) => {
-            // Merge configurations with command line arguments taking precedence
-            let config = Config {
-                directory: ensure_trailing_slash(directory),
-                ip_bind: args
-                    .ip_bind
-                    .or(file_config.ip_bind)
-                    .unwrap_or_else(|| format!("127.0.0.1")),
-                port_bind: args
-                    .port_bind
-                    .or(file_config.port_bind)
-                    .unwrap_or_else(|| 3070),
-                trailer_factor: args
-                    .trailer_factor
-                    .or(file_config.trailer_factor)
-                    .unwrap_or_else(|| 1),
-                poster_factor: args
-                    .poster_factor
-                    .or(file_config.poster_factor)
-                    .unwrap_or_else(|| 1),
-                thumb_factor: args
-                    .thumb_factor
-                    .or(file_config.thumb_factor)
-                    .unwrap_or_else(|| 1),
-                fanart_factor: args
-                    .fanart_factor
-                    .or(file_config.fanart_factor)
-                    .unwrap_or_else(|| 1),
-                video_factor: args
-                    .video_factor
-                    .or(file_config.video_factor)
-                    .unwrap_or_else(|| 0),
-            };
-            let data = Data {
-                movies: load_movie_data(&config.directory),
-                config: config.clone(),
-            };
-            let config_data = web::Data::new(Arc::new(data));
-            let listen = config.ip_bind + ":" + &config.port_bind.to_string();
-            println!("Listening on: http://{}", listen);
-
-            HttpServer::new(move || {
-                App::new()
-                    .app_data(config_data.clone())
-                    .route("/", web::get().to(index))
-                    .route("/grid", web::get().to(grid))
-                    .route("/tv", web::get().to(tv))
-                    .route("/image/{filename:.*}", web::get().to(serve_image))
-                    .route("/movie/{filename:.*}", web::get().to(serve_movie))
-                //.service(fs::Files::new("/static", "./static").show_files_listing())
-            })
-            .bind(listen)?
-            .run()
-            .await
-        }
-        _ => Err(io::Error::new(
-            io::ErrorKind::NotFound,
-            format!("directory not set."),
-        )),
+    if args.hash_password {
+        let mut password = String::new();
+        io::stdin().read_line(&mut password)?;
+        let hash = auth::hash_password(password.trim_end_matches(['\r', '\n']))
+            .map_err(io::Error::other)?;
+        println!("{}", hash);
+        return Ok(());
+    }
+
+    let (config, config_file) = match Config::load(args.config.as_deref(), &args.options) {
+        Ok(loaded) => loaded,
+        Err(e) => {
+            eprintln!("config error: {}", e);
+            std::process::exit(1);
+        }
+    };
+    logging::init(&config.log_level, config.log_format);
+
+    let errors = config.validate();
+    match args.command {
+        Some(Command::Config {
+            action: ConfigCommand::Dump,
+        }) => {
+            print!("{}", config.dump());
+            return Ok(());
+        }
+        Some(Command::Config {
+            action: ConfigCommand::Check,
+        }) if errors.is_empty() => {
+            match config_file {
+                Some(path) => println!("{}: ok", path.display()),
+                None => println!("ok, no config file"),
+            }
+            return Ok(());
+        }
+        _ => {}
+    }
+    if !errors.is_empty() {
+        for e in &errors {
+            eprintln!("config error: {}", e);
+        }
+        std::process::exit(1);
+    }
+
+    if let Some(Command::Scan { report }) = args.command {
+        let movies = library::from_config(&config).load()?.movies;
+        if report {
+            print!(
+                "{}",
+                report::scan_report(&config.directory, config.naming, &movies)
+            );
+        } else {
+            for m in &movies {
+                println!("{}", m.movie.display());
+            }
+        }
+        return Ok(());
+    }
+    let metrics = Arc::new(Metrics::default());
+    let scan_start = std::time::Instant::now();
+    let library = library::from_config(&config);
+    let Library { movies, progress } = library.load()?;
+    metrics.set_scan_duration(scan_start.elapsed());
+    let store = Arc::new(Store::open(config.state_file.clone())?);
+    Store::spawn_writer(store.clone());
+    let auth = match &config.users_file {
+        Some(users_file) => Some(Arc::new(Auth::load(users_file, store.clone())?)),
+        None => None,
+    };
+    if !progress.is_empty() {
+        let profiles = match &auth {
+            Some(auth) => auth.names(),
+            None => vec![DEFAULT_PROFILE.to_string()],
+        };
+        for profile in profiles {
+            store.import_progress(&profile, &progress);
+        }
+    }
+    let data = Data {
+        metrics,
+        files: FileIndex::new(&movies),
+        movies,
+        library,
+        dlna: config.dlna.then(|| {
+            Arc::new(Dlna::new(
+                &config.dlna_name,
+                &config.directory,
+                config.port_bind,
+            ))
+        }),
+        screens: remote::Screens::default(),
+        started: std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|d| d.as_millis() as u64)
+            .unwrap_or_default(),
+        store: store.clone(),
+        auth,
+        signer: if config.access_restricted() {
+            Some(Arc::new(UrlSigner::new(config.signed_url_ttl)))
+        } else {
+            None
+        },
+        config: RwLock::new(Arc::new(config.clone())),
+    };
+    let data = Arc::new(data);
+    if let Some(dlna) = &data.dlna {
+        if config.ip_bind.starts_with("127.") || config.ip_bind == "::1" {
+            tracing::warn!(ip_bind = %config.ip_bind, "dlna needs an ip_bind other devices can reach");
+        }
+        if config.access_restricted() && config.dlna_networks.is_empty() {
+            tracing::warn!("dlna needs dlna_networks for TVs to browse without credentials");
+        }
+        if let Err(e) = dlna::start_ssdp(dlna.clone(), config.ip_bind.clone(), config.port_bind) {
+            tracing::warn!(error = %e, "dlna discovery not available");
+        }
+    }
+    // dropping the watcher stops it
+    let _watcher = match config_file {
+        Some(path) => reload::watch(data.clone(), path, args.options),
+        None => None,
+    };
+    let config_data = web::Data::new(data);
+    let listen = config.ip_bind.clone() + ":" + &config.port_bind.to_string();
+    let tls = match (&config.tls_cert, &config.tls_key) {
+        (Some(cert), Some(key)) => Some(tls::load_server_config(cert, key)?),
+        _ => None,
+    };
+
+    if let (Some(_), Some(redirect_port)) = (&tls, config.http_redirect_port) {
+        let redirect_listen = config.ip_bind.clone() + ":" + &redirect_port.to_string();
+        let https_port = web::Data::new(config.port_bind);
+        tracing::info!("Redirecting http://{} to https", redirect_listen);
+        let redirect = HttpServer::new(move || {
+            App::new()
+                .app_data(https_port.clone())
+                .default_service(web::to(tls::redirect_to_https))
+        })
+        .bind(redirect_listen)?
+        .run();
+        actix_web::rt::spawn(redirect);
+    }
+
+    let server = HttpServer::new(move || {
+        App::new()
+            .app_data(config_data.clone())
+            .wrap(middleware::from_fn(auth::require_login))
+            .wrap(middleware::from_fn(auth::require_credentials))
+            .wrap(middleware::from_fn(metrics::track_requests))
+            .wrap(middleware::from_fn(logging::access_log))
+            .route("/", web::get().to(index))
+            .route("/metrics", web::get().to(metrics::metrics))
+            .route("/login", web::get().to(auth::login_form))
+            .route("/login", web::post().to(auth::login))
+            .route("/logout", web::post().to(auth::logout))
+            .route("/grid", web::get().to(grid))
+            .route("/grid/events", web::get().to(ambient::events))
+            .route("/tv", web::get().to(tv))
+            .route("/remote", web::get().to(remote::remote))
+            .route("/ws/screen", web::get().to(remote::screen_socket))
+            .route("/api/screens", web::get().to(remote::list_screens))
+            .route("/api/screens/{name}", web::post().to(remote::send_command))
+            .route("/playlist.m3u8", web::get().to(playlist::m3u))
+            .route("/playlist.xspf", web::get().to(playlist::xspf))
+            .route("/dlna/description.xml", web::get().to(dlna::description))
+            .route("/dlna/control/{service}", web::post().to(dlna::control))
+            .route("/dlna/event/{service}", web::to(dlna::event))
+            .route(
+                "/dlna/{service}.xml",
+                web::get().to(dlna::service_description),
+            )
+            .route("/image/{filename:.*}", web::get().to(serve_image))
+            .route("/movie/{filename:.*}", web::get().to(serve_movie))
+            .route("/subtitle/{filename:.*}", web::get().to(serve_subtitle))
+            .route("/details/{filename:.*}", web::get().to(details))
+            .route("/api/progress", web::post().to(report_progress))
+            .route("/api/played", web::post().to(report_played))
+            .route("/api/history", web::get().to(history))
+            .route("/api/instance", web::get().to(kiosk::instance))
+            .route("/api/flags/{filename:.*}", web::get().to(get_flags))
+            .route("/api/flags/{filename:.*}", web::post().to(update_flags))
+        //.service(fs::Files::new("/static", "./static").show_files_listing())
+    });
+    let result = match tls {
+        Some(tls) => {
+            tracing::info!("Listening on: https://{}", listen);
+            server.bind_rustls_0_23(listen, tls)?.run().await
+        }
+        None => {
+            tracing::info!("Listening on: http://{}", listen);
+            server.bind(listen)?.run().await
+        }
+    };
+    // the last changes, the writer only runs every few seconds
+    if let Err(e) = store.flush() {
+        tracing::warn!(error = %e, "state not saved");
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    /// Data for handler tests, the state is neither read nor written.
+    pub(crate) fn test_data(movies: Vec<Movie>, config: Config) -> Data {
+        Data {
+            files: FileIndex::new(&movies),
+            movies,
+            library: library::from_config(&config),
+            dlna: Some(Arc::new(Dlna::new("Movies", &config.directory, 3070))),
+            screens: remote::Screens::default(),
+            started: 0,
+            store: Arc::new(Store::open(PathBuf::from("/nonexistent/state.json")).unwrap()),
+            auth: None,
+            signer: None,
+            metrics: Arc::new(Metrics::default()),
+            config: RwLock::new(Arc::new(config)),
+        }
+    }
+
+    /// A scanned movie `<title>/<title>.mp4`.
+    pub(crate) fn test_movie(title: &str) -> Movie {
+        Movie {
+            movie: PathBuf::from(format!("{0}/{0}.mp4", title)),
+            info: MovieInfo {
+                title: Some(title.to_string()),
+                ..Default::default()
+            },
+            ..Default::default()
+        }
+    }
+
+    fn flag(data: &Data, movie: &str, update: FlagsUpdate) {
+        data.store.update_flags(DEFAULT_PROFILE, movie, &update);
+    }
+
+    #[test]
+    fn flags_weigh_the_selection() {
+        let data = test_data(
+            ["A", "B", "C", "D"].into_iter().map(test_movie).collect(),
+            Default::default(),
+        );
+        let profile = Profile(DEFAULT_PROFILE.to_string());
+        let favorite = FlagsUpdate {
+            favorite: Some(true),
+            ..Default::default()
+        };
+        flag(&data, "A/A.mp4", favorite);
+        let disliked = FlagsUpdate {
+            disliked: Some(true),
+            ..Default::default()
+        };
+        flag(&data, "B/B.mp4", disliked);
+        let hidden = FlagsUpdate {
+            hidden: Some(true),
+            favorite: Some(true),
+            ..Default::default()
+        };
+        flag(&data, "C/C.mp4", hidden);
+
+        let config = data.config();
+        let weights: Vec<f64> = data
+            .movies
+            .iter()
+            .map(|m| movie_weight(&data, &profile, None, m))
+            .collect();
+        assert_eq!(
+            weights,
+            [
+                config.favorite_factor as f64,
+                1.0 / config.disliked_factor as f64,
+                0.0,
+                1.0
+            ]
+        );
+        // other profiles don't share the flags
+        let other = Profile("bob".to_string());
+        assert!(data
+            .movies
+            .iter()
+            .all(|m| movie_weight(&data, &other, None, m) == 1.0));
+
+        let titles = |movies: Vec<&Movie>| {
+            let mut titles: Vec<String> = movies.iter().map(|m| m.title()).collect();
+            titles.sort();
+            titles
+        };
+        assert_eq!(
+            titles(choose_movies(&data, &profile, None, 10)),
+            ["A", "B", "D"]
+        );
+        assert_eq!(titles(tv_movies(&data, &profile, None)), ["A", "B", "D"]);
+        assert_eq!(
+            titles(shuffled_tv_movies(&data, &profile, None)),
+            ["A", "B", "D"]
+        );
+
+        let unhidden = FlagsUpdate {
+            hidden: Some(false),
+            ..Default::default()
+        };
+        flag(&data, "C/C.mp4", unhidden);
+        assert_eq!(
+            titles(tv_movies(&data, &profile, None)),
+            ["A", "B", "C", "D"]
+        );
+    }
+
+    #[test]
+    fn favorites_are_picked_more_often() {
+        let data = test_data(
+            ["A", "B"].into_iter().map(test_movie).collect(),
+            Default::default(),
+        );
+        let profile = Profile(DEFAULT_PROFILE.to_string());
+        let favorite = FlagsUpdate {
+            favorite: Some(true),
+            ..Default::default()
+        };
+        flag(&data, "A/A.mp4", favorite);
+        let first = (0..1000)
+            .filter(|_| choose_movies(&data, &profile, None, 1)[0].title() == "A")
+            .count();
+        // expected around 1000 * factor / (factor + 1)
+        assert!(first > 600, "{}", first);
+    }
+
+    #[test]
+    fn encodes_details_urls() {
+        assert_eq!(
+            details_url(Path::new("100% Fun #1? (2000)/100% Fun #1? (2000).mp4")),
+            "/details/100%25%20Fun%20%231%3F%20(2000)/100%25%20Fun%20%231%3F%20(2000).mp4"
+        );
+    }
+
+    #[test]
+    fn variants_of_a_movie() {
+        assert_eq!(variant("Movie", "Movie"), Some(Variant::Main));
+        assert_eq!(variant("Movie", "Movie-cd1"), Some(Variant::Part(1)));
+        assert_eq!(variant("Movie", "Movie part 2"), Some(Variant::Part(2)));
+        assert_eq!(variant("Movie", "Movie.disc-3"), Some(Variant::Part(3)));
+        assert_eq!(variant("Movie", "Movie - 1080p"), Some(Variant::Version));
+        assert_eq!(
+            variant("Movie", "Movie - Director's Cut"),
+            Some(Variant::Version)
+        );
+        assert_eq!(variant("Movie", "Movie.extended"), Some(Variant::Version));
+        assert_eq!(variant("Movie", "Movie-2160p"), Some(Variant::Version));
+    }
+
+    #[test]
+    fn other_movies_are_no_variants() {
+        assert_eq!(variant("Movie", "Movie 2"), None);
+        assert_eq!(variant("Movie", "Movie-cdx"), None);
+        assert_eq!(variant("Movie", "Movie Returns"), None);
+        assert_eq!(variant("Alien", "Aliens"), None);
+        assert_eq!(variant("Alien", "Alien"), Some(Variant::Main));
+        assert_eq!(variant("Movie", "Other"), None);
+    }
+
+    fn scan(files: &[&str]) -> Vec<Movie> {
+        let dir = tempfile::tempdir().unwrap();
+        let root = format!("{}/", dir.path().display());
+        fs::create_dir_all(dir.path().join("Movie")).unwrap();
+        for file in files {
+            fs::write(dir.path().join("Movie").join(file), "").unwrap();
+        }
+        let mut movies = load_movie_data(&root, Naming::Kodi);
+        movies.sort_by(|a, b| a.movie.cmp(&b.movie));
+        movies
+    }
+
+    fn paths(paths: &[PathBuf]) -> Vec<String> {
+        paths.iter().map(|p| p.display().to_string()).collect()
+    }
+
+    #[test]
+    fn groups_main_file_stack_and_versions() {
+        let movies = scan(&[
+            "Movie.mp4",
+            "Movie-cd1.mp4",
+            "Movie-cd2.mp4",
+            "Movie - 1080p.mp4",
+            "Movie 2.mp4",
+            "Movie-trailer.mp4",
+        ]);
+        assert_eq!(movies.len(), 2);
+        assert_eq!(movies[0].movie, PathBuf::from("Movie/Movie 2.mp4"));
+        assert!(movies[0].versions.is_empty());
+
+        let movie = &movies[1];
+        assert_eq!(movie.movie, PathBuf::from("Movie/Movie.mp4"));
+        assert!(movie.parts.is_empty());
+        assert_eq!(movie.versions.len(), 2);
+        assert_eq!(
+            movie.versions[0].path,
+            PathBuf::from("Movie/Movie - 1080p.mp4")
+        );
+        assert!(movie.versions[0].parts.is_empty());
+        assert_eq!(paths(&movie.trailer), ["Movie/Movie-trailer.mp4"]);
+    }
+
+    #[test]
+    fn suffixed_extras_are_no_versions() {
+        let movies = scan(&["Movie.mp4", "Movie-featurette.mp4"]);
+        assert_eq!(movies.len(), 1);
+        assert!(movies[0].versions.is_empty());
+        assert_eq!(movies[0].extras.len(), 1);
+        assert_eq!(movies[0].extras[0].kind, ExtraKind::Featurette);
+    }
+
+    #[test]
+    fn keeps_a_stack_next_to_a_single_file_together() {
+        let movies = scan(&["Movie.mp4", "Movie-cd1.mp4", "Movie-cd2.mp4"]);
+        assert_eq!(movies.len(), 1);
+        let movie = &movies[0];
+        assert_eq!(movie.movie, PathBuf::from("Movie/Movie.mp4"));
+        assert!(movie.parts.is_empty());
+        // one version playing both parts, not a version per part
+        assert_eq!(movie.versions.len(), 1);
+        assert_eq!(movie.versions[0].path, PathBuf::from("Movie/Movie-cd1.mp4"));
+        assert_eq!(paths(&movie.versions[0].parts), ["Movie/Movie-cd2.mp4"]);
+        let index = FileIndex::new(&movies);
+        assert!(index.get(Path::new("Movie/Movie-cd2.mp4")).is_some());
+    }
+
+    #[test]
+    fn stacks_parts_in_order() {
+        let movies = scan(&["Movie-cd2.mp4", "Movie-cd10.mp4", "Movie-cd1.mp4"]);
+        assert_eq!(movies.len(), 1);
+        assert_eq!(movies[0].movie, PathBuf::from("Movie/Movie-cd1.mp4"));
+        assert_eq!(
+            paths(&movies[0].parts),
+            ["Movie/Movie-cd2.mp4", "Movie/Movie-cd10.mp4"]
+        );
     }
 }
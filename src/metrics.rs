@@ -0,0 +1,314 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+
+use actix_web::body::{BodySize, BoxBody, MessageBody};
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::middleware::Next;
+use actix_web::web::{self, Bytes};
+use actix_web::{Error, HttpResponse};
+
+use crate::Data;
+
+/// Upper bounds of the request duration histogram in seconds.
+const BUCKETS: [f64; 11] = [
+    0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+];
+
+#[derive(Debug, Default)]
+struct Histogram {
+    count: u64,
+    sum: f64,
+    buckets: [u64; BUCKETS.len()],
+}
+
+impl Histogram {
+    fn observe(&mut self, seconds: f64) {
+        self.count += 1;
+        self.sum += seconds;
+        for (bucket, le) in self.buckets.iter_mut().zip(BUCKETS) {
+            if seconds <= le {
+                *bucket += 1;
+            }
+        }
+    }
+}
+
+/// Counters exposed on `/metrics` in the Prometheus text format.
+#[derive(Debug, Default)]
+pub struct Metrics {
+    scan_duration_ms: AtomicU64,
+    bytes_streamed: AtomicU64,
+    active_streams: AtomicU64,
+    /// Keyed by (method, route, status).
+    requests: Mutex<BTreeMap<(String, String, u16), u64>>,
+    /// Keyed by (method, route).
+    durations: Mutex<BTreeMap<(String, String), Histogram>>,
+}
+
+impl Metrics {
+    pub fn set_scan_duration(&self, duration: Duration) {
+        self.scan_duration_ms
+            .store(duration.as_millis() as u64, Ordering::Relaxed);
+    }
+
+    fn observe_request(&self, method: &str, route: &str, status: u16, duration: Duration) {
+        *self
+            .requests
+            .lock()
+            .unwrap()
+            .entry((method.to_string(), route.to_string(), status))
+            .or_default() += 1;
+        self.durations
+            .lock()
+            .unwrap()
+            .entry((method.to_string(), route.to_string()))
+            .or_default()
+            .observe(duration.as_secs_f64());
+    }
+
+    fn render(&self, data: &Data) -> String {
+        let mut out = String::new();
+
+        let movies = &data.movies;
+        let library = [
+            ("video", movies.len()),
+            ("trailer", movies.iter().map(|m| m.trailer.len()).sum()),
+            ("poster", movies.iter().map(|m| m.poster.len()).sum()),
+            ("thumb", movies.iter().map(|m| m.thumb.len()).sum()),
+            ("fanart", movies.iter().map(|m| m.fanarts.len()).sum()),
+            ("clearlogo", movies.iter().map(|m| m.clearlogo.len()).sum()),
+            ("clearart", movies.iter().map(|m| m.clearart.len()).sum()),
+            ("banner", movies.iter().map(|m| m.banner.len()).sum()),
+            ("landscape", movies.iter().map(|m| m.landscape.len()).sum()),
+            ("disc", movies.iter().map(|m| m.disc.len()).sum()),
+            ("extra", movies.iter().map(|m| m.extras.len()).sum()),
+        ];
+        out.push_str("# HELP rvs_library_files Files found by the last library scan.\n");
+        out.push_str("# TYPE rvs_library_files gauge\n");
+        for (kind, n) in library {
+            let _ = writeln!(out, "rvs_library_files{{type=\"{}\"}} {}", kind, n);
+        }
+
+        out.push_str("# HELP rvs_scan_duration_seconds Duration of the last library scan.\n");
+        out.push_str("# TYPE rvs_scan_duration_seconds gauge\n");
+        let _ = writeln!(
+            out,
+            "rvs_scan_duration_seconds {}",
+            self.scan_duration_ms.load(Ordering::Relaxed) as f64 / 1000.0
+        );
+
+        out.push_str("# HELP rvs_http_requests_total Handled requests.\n");
+        out.push_str("# TYPE rvs_http_requests_total counter\n");
+        for ((method, route, status), n) in self.requests.lock().unwrap().iter() {
+            let _ = writeln!(
+                out,
+                "rvs_http_requests_total{{method=\"{}\",route=\"{}\",status=\"{}\"}} {}",
+                method,
+                escape_label(route),
+                status,
+                n
+            );
+        }
+
+        out.push_str(
+            "# HELP rvs_http_request_duration_seconds Time until the response head was ready.\n",
+        );
+        out.push_str("# TYPE rvs_http_request_duration_seconds histogram\n");
+        for ((method, route), h) in self.durations.lock().unwrap().iter() {
+            let labels = format!("method=\"{}\",route=\"{}\"", method, escape_label(route));
+            for (le, n) in BUCKETS.iter().zip(h.buckets) {
+                let _ = writeln!(
+                    out,
+                    "rvs_http_request_duration_seconds_bucket{{{},le=\"{}\"}} {}",
+                    labels, le, n
+                );
+            }
+            let _ = writeln!(
+                out,
+                "rvs_http_request_duration_seconds_bucket{{{},le=\"+Inf\"}} {}",
+                labels, h.count
+            );
+            let _ = writeln!(
+                out,
+                "rvs_http_request_duration_seconds_sum{{{}}} {}",
+                labels, h.sum
+            );
+            let _ = writeln!(
+                out,
+                "rvs_http_request_duration_seconds_count{{{}}} {}",
+                labels, h.count
+            );
+        }
+
+        out.push_str("# HELP rvs_streamed_bytes_total Bytes of video sent by /movie.\n");
+        out.push_str("# TYPE rvs_streamed_bytes_total counter\n");
+        let _ = writeln!(
+            out,
+            "rvs_streamed_bytes_total {}",
+            self.bytes_streamed.load(Ordering::Relaxed)
+        );
+
+        out.push_str("# HELP rvs_active_streams Videos currently being sent by /movie.\n");
+        out.push_str("# TYPE rvs_active_streams gauge\n");
+        let _ = writeln!(
+            out,
+            "rvs_active_streams {}",
+            self.active_streams.load(Ordering::Relaxed)
+        );
+
+        out
+    }
+}
+
+fn escape_label(s: &str) -> String {
+    s.replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+pub async fn metrics(data: web::Data<Arc<Data>>) -> HttpResponse {
+    HttpResponse::Ok()
+        .content_type("text/plain; version=0.0.4")
+        .body(data.metrics.render(&data))
+}
+
+/// Middleware counting requests and their durations per route pattern, so that
+/// `/movie/{path}` doesn't create a series per file.
+pub async fn track_requests(
+    req: ServiceRequest,
+    next: Next<BoxBody>,
+) -> Result<ServiceResponse<BoxBody>, Error> {
+    let start = Instant::now();
+    let method = req.method().to_string();
+    let metrics = req
+        .app_data::<web::Data<Arc<Data>>>()
+        .map(|d| d.metrics.clone());
+
+    let res = next.call(req).await?;
+
+    if let Some(metrics) = metrics {
+        let route = res
+            .request()
+            .match_pattern()
+            .unwrap_or_else(|| "unmatched".to_string());
+        metrics.observe_request(&method, &route, res.status().as_u16(), start.elapsed());
+    }
+    Ok(res)
+}
+
+/// Body counting the bytes sent, an active stream until dropped.
+pub struct StreamBody<B> {
+    body: B,
+    metrics: Arc<Metrics>,
+}
+
+impl<B> StreamBody<B> {
+    pub fn new(body: B, metrics: Arc<Metrics>) -> StreamBody<B> {
+        metrics.active_streams.fetch_add(1, Ordering::Relaxed);
+        StreamBody { body, metrics }
+    }
+}
+
+impl<B> Drop for StreamBody<B> {
+    fn drop(&mut self) {
+        self.metrics.active_streams.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+impl<B: MessageBody + Unpin> MessageBody for StreamBody<B> {
+    type Error = B::Error;
+
+    fn size(&self) -> BodySize {
+        self.body.size()
+    }
+
+    fn poll_next(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Bytes, Self::Error>>> {
+        let this = self.get_mut();
+        let poll = Pin::new(&mut this.body).poll_next(cx);
+        if let Poll::Ready(Some(Ok(bytes))) = &poll {
+            this.metrics
+                .bytes_streamed
+                .fetch_add(bytes.len() as u64, Ordering::Relaxed);
+        }
+        poll
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[actix_web::test]
+    async fn counts_requests_per_route() {
+        use actix_web::{middleware, test, App};
+
+        let data = Arc::new(crate::tests::test_data(
+            vec![crate::tests::test_movie("A")],
+            Default::default(),
+        ));
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(data.clone()))
+                .wrap(middleware::from_fn(track_requests))
+                .route("/movie/{path:.*}", web::get().to(HttpResponse::Ok)),
+        )
+        .await;
+        for uri in ["/movie/A/A.mp4", "/movie/B/B.mp4", "/missing"] {
+            test::call_service(&app, test::TestRequest::with_uri(uri).to_request()).await;
+        }
+
+        let text = data.metrics.render(&data);
+        for line in [
+            "rvs_library_files{type=\"video\"} 1",
+            "rvs_http_requests_total{method=\"GET\",route=\"/movie/{path:.*}\",status=\"200\"} 2",
+            "rvs_http_requests_total{method=\"GET\",route=\"unmatched\",status=\"404\"} 1",
+            "rvs_http_request_duration_seconds_bucket{method=\"GET\",route=\"/movie/{path:.*}\",le=\"+Inf\"} 2",
+            "rvs_http_request_duration_seconds_count{method=\"GET\",route=\"unmatched\"} 1",
+            "rvs_active_streams 0",
+        ] {
+            assert!(text.lines().any(|l| l == line), "{}\n{}", line, text);
+        }
+    }
+
+    #[test]
+    fn renders_the_text_format() {
+        let data = crate::tests::test_data(Vec::new(), Default::default());
+        let metrics = &data.metrics;
+        metrics.set_scan_duration(Duration::from_millis(1500));
+        metrics.observe_request("GET", "/a\"b", 200, Duration::from_millis(20));
+        metrics.bytes_streamed.store(42, Ordering::Relaxed);
+
+        let text = metrics.render(&data);
+        let labels = "method=\"GET\",route=\"/a\\\"b\"";
+        for line in [
+            "# TYPE rvs_scan_duration_seconds gauge".to_string(),
+            "rvs_scan_duration_seconds 1.5".to_string(),
+            format!("rvs_http_requests_total{{{},status=\"200\"}} 1", labels),
+            format!(
+                "rvs_http_request_duration_seconds_bucket{{{},le=\"0.01\"}} 0",
+                labels
+            ),
+            format!(
+                "rvs_http_request_duration_seconds_bucket{{{},le=\"0.025\"}} 1",
+                labels
+            ),
+            format!(
+                "rvs_http_request_duration_seconds_bucket{{{},le=\"10\"}} 1",
+                labels
+            ),
+            format!("rvs_http_request_duration_seconds_sum{{{}}} 0.02", labels),
+            "rvs_streamed_bytes_total 42".to_string(),
+        ] {
+            assert!(text.lines().any(|l| l == line), "{}\n{}", line, text);
+        }
+        assert_eq!(escape_label("a\\b\nc"), "a\\\\b\\nc");
+    }
+}
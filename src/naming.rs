@@ -0,0 +1,218 @@
+use std::path::Path;
+
+use clap::ValueEnum;
+use glob::Pattern;
+use serde::{Deserialize, Serialize};
+
+use crate::nfo::{load_nfo, MovieInfo};
+use crate::remove_extension;
+
+/// How the files of a movie folder are named, the scanner asks it where to look.
+pub trait NamingConvention: Sync {
+    /// The name the videos of a movie start with, from the folder or file `name`.
+    fn movie_name(&self, name: &str) -> String;
+
+    /// Glob patterns for the artwork `kind` (`poster`, `fanart`, ...) of the movie
+    /// whose files are named `base`.
+    fn artwork(&self, base: &str, kind: &str) -> Vec<String>;
+
+    /// Metadata of the movie `base` with the main video `video`.
+    fn info(&self, base: &str, video: &Path) -> MovieInfo;
+}
+
+/// The naming convention of the library root, `directory`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, ValueEnum)]
+#[serde(rename_all = "snake_case")]
+pub enum Naming {
+    /// Per movie folder, Jellyfin if it has provider ids or Jellyfin artwork names.
+    #[default]
+    Auto,
+    /// `Movie (2000)/Movie (2000).mp4` with `Movie (2000)-poster.jpg`, ...
+    Kodi,
+    /// `Movie (2000) [imdbid-tt0000000]/` with `poster.jpg`, `backdrop.jpg`, ...
+    Jellyfin,
+}
+
+impl Naming {
+    /// The convention of the movie folder `folder`.
+    pub fn for_folder(self, folder: &Path) -> &'static dyn NamingConvention {
+        match self {
+            Naming::Kodi => &Kodi,
+            Naming::Jellyfin => &Jellyfin,
+            Naming::Auto if looks_like_jellyfin(folder) => &Jellyfin,
+            Naming::Auto => &Kodi,
+        }
+    }
+}
+
+const PROVIDER_IDS: [&str; 6] = [
+    "imdbid-", "tmdbid-", "tvdbid-", "{imdb-", "{tmdb-", "{tvdb-",
+];
+/// Artwork only Jellyfin names like this, Kodi also knows `poster.jpg` and `fanart.jpg`.
+const JELLYFIN_ARTWORK: [&str; 4] = ["folder", "backdrop", "cover", "logo"];
+
+/// A folder named with provider ids or containing Jellyfin's artwork names. Jellyfin
+/// also reads the Kodi names, so a wrong guess only costs a few globs.
+fn looks_like_jellyfin(folder: &Path) -> bool {
+    let name = folder
+        .file_name()
+        .map(|n| n.to_string_lossy().to_lowercase())
+        .unwrap_or_default();
+    if PROVIDER_IDS.iter().any(|id| name.contains(id)) {
+        return true;
+    }
+    std::fs::read_dir(folder)
+        .map(|entries| {
+            entries.flatten().any(|e| {
+                let path = e.path();
+                let stem = path
+                    .file_stem()
+                    .map(|s| s.to_string_lossy().to_lowercase())
+                    .unwrap_or_default();
+                let ext = path
+                    .extension()
+                    .map(|s| s.to_string_lossy().to_lowercase())
+                    .unwrap_or_default();
+                JELLYFIN_ARTWORK.contains(&stem.as_str()) && ["jpg", "png"].contains(&ext.as_str())
+            })
+        })
+        .unwrap_or(false)
+}
+
+/// `<base>-<kind>*.jpg` and `.png`
+fn prefixed_artwork(base: &str, kind: &str) -> Vec<String> {
+    let base = Pattern::escape(base);
+    vec![
+        format!("{}-{}*.jpg", base, kind),
+        format!("{}-{}*.png", base, kind),
+    ]
+}
+
+pub struct Kodi;
+
+impl NamingConvention for Kodi {
+    fn movie_name(&self, name: &str) -> String {
+        name.to_string()
+    }
+
+    fn artwork(&self, base: &str, kind: &str) -> Vec<String> {
+        prefixed_artwork(base, kind)
+    }
+
+    fn info(&self, base: &str, _video: &Path) -> MovieInfo {
+        // `<base>.nfo` also for stacked and versioned files
+        load_nfo(Path::new(&(base.to_owned() + ".mp4")))
+    }
+}
+
+pub struct Jellyfin;
+
+impl NamingConvention for Jellyfin {
+    /// Drops provider ids like `[imdbid-tt0000000]` or `{tmdb-123}`.
+    fn movie_name(&self, name: &str) -> String {
+        let mut out = String::new();
+        let mut depth = 0;
+        for c in name.chars() {
+            match c {
+                '[' | '{' => depth += 1,
+                ']' | '}' if depth > 0 => depth -= 1,
+                _ if depth == 0 => out.push(c),
+                _ => {}
+            }
+        }
+        out.split_whitespace().collect::<Vec<_>>().join(" ")
+    }
+
+    fn artwork(&self, base: &str, kind: &str) -> Vec<String> {
+        let names: &[&str] = match kind {
+            "poster" => &["poster", "folder", "cover"],
+            "fanart" => &["backdrop", "fanart", "background"],
+            "clearlogo" => &["logo", "clearlogo"],
+            other => &[other][..],
+        };
+        let folder = Path::new(base)
+            .parent()
+            .map(|p| Pattern::escape(&p.to_string_lossy()))
+            .unwrap_or_default();
+        let mut patterns = Vec::new();
+        for name in names {
+            for ext in ["jpg", "png"] {
+                patterns.push(format!("{}/{}*.{}", folder, name, ext));
+            }
+        }
+        // Jellyfin also reads the Kodi names
+        patterns.extend(prefixed_artwork(base, kind));
+        patterns
+    }
+
+    fn info(&self, base: &str, video: &Path) -> MovieInfo {
+        // the video keeps the provider ids, `<video>.nfo` or `movie.nfo`
+        let own = Path::new(&(remove_extension(video) + ".nfo")).exists()
+            || video.with_file_name("movie.nfo").exists();
+        if own {
+            load_nfo(video)
+        } else {
+            Kodi.info(base, video)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn jellyfin_drops_provider_ids() {
+        let jellyfin = Naming::Jellyfin.for_folder(Path::new("/movies"));
+        assert_eq!(
+            jellyfin.movie_name("Movie (2000) [imdbid-tt0000000]"),
+            "Movie (2000)"
+        );
+        assert_eq!(
+            jellyfin.movie_name("Movie (2000) [tmdbid-1] - 1080p"),
+            "Movie (2000) - 1080p"
+        );
+        assert_eq!(jellyfin.movie_name("Movie {tmdb-1} (2000)"), "Movie (2000)");
+        assert_eq!(
+            Naming::Kodi
+                .for_folder(Path::new("/movies"))
+                .movie_name("Movie [2000]"),
+            "Movie [2000]"
+        );
+    }
+
+    #[test]
+    fn jellyfin_artwork_in_the_folder() {
+        let patterns = Naming::Jellyfin
+            .for_folder(Path::new("/movies"))
+            .artwork("/movies/Movie (2000) [imdbid-tt1]/Movie (2000)", "fanart");
+        assert!(
+            patterns.contains(&"/movies/Movie (2000) [[]imdbid-tt1[]]/backdrop*.jpg".to_string())
+        );
+        assert!(patterns.contains(
+            &"/movies/Movie (2000) [[]imdbid-tt1[]]/Movie (2000)-fanart*.png".to_string()
+        ));
+    }
+
+    #[test]
+    fn detects_jellyfin_folders() {
+        let dir = tempfile::tempdir().unwrap();
+        let folder = |name: &str, files: &[&str]| {
+            let folder = dir.path().join(name);
+            std::fs::create_dir_all(&folder).unwrap();
+            for file in files {
+                std::fs::write(folder.join(file), "").unwrap();
+            }
+            folder
+        };
+        let kodi = folder("Kodi (2000)", &["Kodi (2000).mp4", "poster.jpg"]);
+        let ids = folder("Ids (2000) [imdbid-tt1]", &["Ids (2000).mp4"]);
+        let art = folder("Art (2000)", &["Art (2000).mp4", "Backdrop.JPG"]);
+
+        let name = |folder: &Path| Naming::Auto.for_folder(folder).movie_name("A [b]");
+        assert_eq!(name(&kodi), "A [b]");
+        assert_eq!(name(&ids), "A");
+        assert_eq!(name(&art), "A");
+        assert_eq!(Naming::Kodi.for_folder(&ids).movie_name("A [b]"), "A [b]");
+    }
+}
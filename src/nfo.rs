@@ -0,0 +1,79 @@
+use std::fs::read_to_string;
+use std::path::Path;
+
+use crate::remove_extension;
+
+/// Metadata from a Kodi `.nfo` file.
+#[derive(Debug, Clone, Default)]
+pub struct MovieInfo {
+    pub title: Option<String>,
+    pub original_title: Option<String>,
+    pub year: Option<String>,
+    pub tagline: Option<String>,
+    pub plot: Option<String>,
+    pub runtime: Option<String>,
+    pub rating: Option<String>,
+    /// e.g. `Rated PG-13` or `DE:FSK 12`
+    pub certification: Option<String>,
+    pub genres: Vec<String>,
+    pub directors: Vec<String>,
+}
+
+/// Reads `<name>.nfo` or `movie.nfo` next to the video.
+pub fn load_nfo(video: &Path) -> MovieInfo {
+    let mut candidates = vec![remove_extension(video) + ".nfo"];
+    if let Some(parent) = video.parent() {
+        candidates.push(parent.join("movie.nfo").to_string_lossy().to_string());
+    }
+    candidates
+        .iter()
+        .find_map(|c| read_to_string(c).ok())
+        .map(|content| parse_nfo(&content))
+        .unwrap_or_default()
+}
+
+fn parse_nfo(content: &str) -> MovieInfo {
+    // Kodi allows a scraper url after the xml, cut it off
+    let content = match content.find("</movie>") {
+        Some(end) => &content[..end + "</movie>".len()],
+        None => content,
+    };
+    let doc = match roxmltree::Document::parse(content) {
+        Ok(doc) => doc,
+        Err(_) => return MovieInfo::default(),
+    };
+    let root = doc.root_element();
+    let texts = |tag: &str| -> Vec<String> {
+        root.children()
+            .filter(|n| n.has_tag_name(tag))
+            .filter_map(|n| n.text())
+            .map(|t| t.trim().to_string())
+            .filter(|t| !t.is_empty())
+            .collect()
+    };
+    let text = |tag: &str| texts(tag).into_iter().next();
+
+    // <rating>7.5</rating> or <ratings><rating default="true"><value>7.5</value></rating></ratings>
+    let rating = text("rating").or_else(|| {
+        root.descendants()
+            .filter(|n| n.has_tag_name("rating"))
+            .find(|n| n.attribute("default") == Some("true"))
+            .or_else(|| root.descendants().find(|n| n.has_tag_name("rating")))
+            .and_then(|n| n.children().find(|c| c.has_tag_name("value")))
+            .and_then(|n| n.text())
+            .map(|t| t.trim().to_string())
+    });
+
+    MovieInfo {
+        title: text("title"),
+        original_title: text("originaltitle"),
+        year: text("year").or_else(|| text("premiered").map(|p| p.chars().take(4).collect())),
+        tagline: text("tagline"),
+        plot: text("plot").or_else(|| text("outline")),
+        runtime: text("runtime"),
+        rating,
+        certification: text("mpaa").or_else(|| text("certification")),
+        genres: texts("genre"),
+        directors: texts("director"),
+    }
+}
@@ -0,0 +1,119 @@
+use crate::nfo::MovieInfo;
+
+/// Minimum age for a certification like `Rated PG-13`, `US:R`, `DE:FSK 12` or `16`.
+pub fn certification_age(certification: &str) -> Option<u8> {
+    // Kodi prefixes the country, e.g. "US:PG-13 / DE:12", use the first one
+    let certification = certification.split('/').next()?;
+    let certification = certification
+        .rsplit(':')
+        .next()?
+        .trim()
+        .to_uppercase()
+        .replace("RATED", "")
+        .replace("FSK", "")
+        .replace("AB", "")
+        .trim()
+        .to_string();
+    let age = match certification.as_str() {
+        "G" | "U" | "TV-Y" | "TV-G" | "0" | "O.A." => 0,
+        "TV-Y7" => 7,
+        "PG" | "TV-PG" => 8,
+        "PG-13" | "12A" => 13,
+        "TV-14" => 14,
+        "R" => 17,
+        "NC-17" | "TV-MA" | "X" => 18,
+        c => c
+            .split(|ch: char| !ch.is_ascii_digit())
+            .find(|n| !n.is_empty())?
+            .parse()
+            .ok()?,
+    };
+    Some(age)
+}
+
+/// Which certifications may be shown.
+#[derive(Debug, Clone, Default)]
+pub struct Restriction {
+    pub max_age: Option<u8>,
+    pub allow_unrated: bool,
+    /// A limit was set but not understood, nothing may be shown.
+    pub deny_all: bool,
+}
+
+impl Restriction {
+    pub fn new(max_certification: Option<&str>, allow_unrated: bool) -> Restriction {
+        let max_age = max_certification.and_then(certification_age);
+        Restriction {
+            max_age,
+            allow_unrated,
+            // validation rejects these, fail closed should one get through
+            deny_all: max_certification.is_some() && max_age.is_none(),
+        }
+    }
+
+    /// The stricter of both restrictions.
+    pub fn and(&self, other: &Restriction) -> Restriction {
+        Restriction {
+            max_age: match (self.max_age, other.max_age) {
+                (Some(a), Some(b)) => Some(a.min(b)),
+                (a, b) => a.or(b),
+            },
+            allow_unrated: self.allow_unrated && other.allow_unrated,
+            deny_all: self.deny_all || other.deny_all,
+        }
+    }
+
+    pub fn allows(&self, info: &MovieInfo) -> bool {
+        if self.deny_all {
+            return false;
+        }
+        match self.max_age {
+            None => true,
+            Some(max_age) => match info.certification.as_deref().and_then(certification_age) {
+                Some(age) => age <= max_age,
+                None => self.allow_unrated,
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn certification_ages() {
+        assert_eq!(certification_age("US:PG-13 / DE:12"), Some(13));
+        assert_eq!(certification_age("DE:12 / US:PG-13"), Some(12));
+        assert_eq!(certification_age("FSK 16"), Some(16));
+        assert_eq!(certification_age("DE:FSK 0"), Some(0));
+        assert_eq!(certification_age("Rated R"), Some(17));
+        assert_eq!(certification_age("NC-17"), Some(18));
+        assert_eq!(certification_age("12A"), Some(13));
+        assert_eq!(certification_age("ab 6"), Some(6));
+        // the number is enough
+        assert_eq!(certification_age("PG13"), Some(13));
+        assert_eq!(certification_age("kids"), None);
+        assert_eq!(certification_age("Unrated"), None);
+        assert_eq!(certification_age(""), None);
+    }
+
+    #[test]
+    fn unknown_limit_denies_all() {
+        let movie = |certification: Option<&str>| MovieInfo {
+            certification: certification.map(|c| c.to_string()),
+            ..Default::default()
+        };
+        let restriction = Restriction::new(Some("kids"), true);
+        assert!(!restriction.allows(&movie(Some("G"))));
+        assert!(!restriction.allows(&movie(None)));
+        let stricter = Restriction::new(Some("R"), true).and(&restriction);
+        assert!(!stricter.allows(&movie(Some("G"))));
+
+        let restriction = Restriction::new(Some("PG-13"), false);
+        assert!(restriction.allows(&movie(Some("PG"))));
+        assert!(!restriction.allows(&movie(Some("R"))));
+        assert!(!restriction.allows(&movie(None)));
+        assert!(Restriction::new(None, false).allows(&movie(Some("NC-17"))));
+    }
+}
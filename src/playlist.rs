@@ -0,0 +1,218 @@
+use std::fmt::Write;
+use std::sync::Arc;
+
+use actix_web::{web, HttpRequest, HttpResponse};
+use serde::Deserialize;
+
+use crate::auth::Profile;
+use crate::{base_url, escape_html, find_channel, media_url, shuffled_tv_movies, Data};
+
+#[derive(Debug, Deserialize)]
+pub struct PlaylistQuery {
+    channel: Option<String>,
+    /// At most this many entries, all movies by default.
+    limit: Option<usize>,
+}
+
+/// One playlist entry with absolute urls.
+struct Entry {
+    title: String,
+    url: String,
+    image: Option<String>,
+    plot: Option<String>,
+    seconds: Option<u64>,
+}
+
+/// The movies `/tv` would play, favorites early more often.
+fn entries(req: &HttpRequest, data: &Data, profile: &Profile, query: &PlaylistQuery) -> Vec<Entry> {
+    let channel = find_channel(data, &query.channel);
+    let mut movies = shuffled_tv_movies(data, profile, channel.as_ref());
+    if let Some(limit) = query.limit {
+        movies.truncate(limit);
+    }
+
+    let base = base_url(req);
+    movies
+        .into_iter()
+        .map(|m| Entry {
+            title: m.title_with_year(),
+            url: base.clone() + &media_url(data, profile, "movie", &m.movie),
+            image: m
+                .poster
+                .iter()
+                .chain(&m.thumb)
+                .chain(&m.fanarts)
+                .next()
+                .map(|p| base.clone() + &media_url(data, profile, "image", p)),
+            plot: m.info.plot.clone(),
+            seconds: m
+                .info
+                .runtime
+                .as_ref()
+                .and_then(|r| r.trim().parse::<u64>().ok())
+                .map(|minutes| minutes * 60),
+        })
+        .collect()
+}
+
+/// Extended M3U for VLC, mpv and IPTV players.
+pub async fn m3u(
+    req: HttpRequest,
+    data: web::Data<Arc<Data>>,
+    profile: Profile,
+    query: web::Query<PlaylistQuery>,
+) -> HttpResponse {
+    let mut out = String::from("#EXTM3U\n");
+    for e in entries(&req, &data, &profile, &query) {
+        let logo = e
+            .image
+            .map(|i| format!(" tvg-logo=\"{}\"", i.replace('"', "%22")))
+            .unwrap_or_default();
+        let seconds = e.seconds.map(|s| s as i64).unwrap_or(-1);
+        // the title ends the line, a newline would start the next entry
+        let title = e.title.replace(['\r', '\n'], " ");
+        let _ = writeln!(out, "#EXTINF:{}{},{}", seconds, logo, title);
+        let _ = writeln!(out, "{}", e.url);
+    }
+    HttpResponse::Ok()
+        .content_type("audio/x-mpegurl; charset=utf-8")
+        .body(out)
+}
+
+/// XSPF, the playlist format VLC saves.
+pub async fn xspf(
+    req: HttpRequest,
+    data: web::Data<Arc<Data>>,
+    profile: Profile,
+    query: web::Query<PlaylistQuery>,
+) -> HttpResponse {
+    let mut out = String::from(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+         <playlist version=\"1\" xmlns=\"http://xspf.org/ns/0/\">\n\
+         <title>Random Video Server</title>\n\
+         <trackList>\n",
+    );
+    for e in entries(&req, &data, &profile, &query) {
+        out.push_str("<track>");
+        let _ = write!(out, "<location>{}</location>", escape_html(&e.url));
+        let _ = write!(out, "<title>{}</title>", escape_html(&e.title));
+        if let Some(image) = e.image {
+            let _ = write!(out, "<image>{}</image>", escape_html(&image));
+        }
+        if let Some(plot) = e.plot {
+            let _ = write!(out, "<annotation>{}</annotation>", escape_html(&plot));
+        }
+        if let Some(seconds) = e.seconds {
+            let _ = write!(out, "<duration>{}</duration>", seconds * 1000);
+        }
+        out.push_str("</track>\n");
+    }
+    out.push_str("</trackList>\n</playlist>\n");
+    HttpResponse::Ok()
+        .content_type("application/xspf+xml; charset=utf-8")
+        .body(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::auth::UrlSigner;
+    use crate::tests::{test_data, test_movie};
+    use actix_web::body;
+    use actix_web::test::TestRequest;
+    use std::path::PathBuf;
+
+    /// The body of `handler` for one movie `Tom & Jerry` with artwork and a plot, and one
+    /// with a line break in its title, as seen by a client of `https://movies.example`.
+    async fn playlist<F, R>(handler: F) -> String
+    where
+        F: Fn(HttpRequest, web::Data<Arc<Data>>, Profile, web::Query<PlaylistQuery>) -> R,
+        R: std::future::Future<Output = HttpResponse>,
+    {
+        let mut movie = test_movie("Tom & Jerry");
+        movie.info.year = Some("1992".to_string());
+        movie.info.plot = Some("Cat <3 mouse".to_string());
+        movie.info.runtime = Some("90".to_string());
+        movie.poster = vec![PathBuf::from("Tom & Jerry/poster \"1\".jpg")];
+        let mut broken = test_movie("Broken");
+        broken.info.title = Some("Line\nbreak".to_string());
+        let data = Data {
+            signer: Some(Arc::new(UrlSigner::new(60))),
+            ..test_data(vec![movie, broken], Default::default())
+        };
+        let req = TestRequest::default()
+            .insert_header(("Host", "movies.example"))
+            .insert_header(("X-Forwarded-Proto", "https"))
+            .to_http_request();
+        let query = web::Query(PlaylistQuery {
+            channel: None,
+            limit: None,
+        });
+        let res = handler(
+            req,
+            web::Data::new(Arc::new(data)),
+            Profile("alice".to_string()),
+            query,
+        )
+        .await;
+        let body = body::to_bytes(res.into_body()).await.unwrap();
+        String::from_utf8(body.to_vec()).unwrap()
+    }
+
+    #[actix_web::test]
+    async fn m3u_lists_signed_absolute_urls() {
+        let out = playlist(m3u).await;
+        let lines: Vec<&str> = out.lines().collect();
+        assert_eq!(lines[0], "#EXTM3U");
+        assert_eq!(lines.len(), 5, "{}", out);
+        let tom = lines
+            .iter()
+            .position(|l| l.ends_with(",Tom & Jerry (1992)"))
+            .unwrap();
+        assert!(lines[tom].starts_with(
+            "#EXTINF:5400 tvg-logo=\"https://movies.example/image/Tom%20&%20Jerry/poster%20%221%22.jpg?profile=alice&expires="
+        ));
+        assert!(lines[tom + 1].starts_with(
+            "https://movies.example/movie/Tom%20&%20Jerry/Tom%20&%20Jerry.mp4?profile=alice&expires="
+        ));
+        assert!(lines[tom + 1].contains("&signature="));
+        let broken = lines
+            .iter()
+            .position(|l| l.starts_with("#EXTINF:-1,"))
+            .unwrap();
+        assert_eq!(lines[broken], "#EXTINF:-1,Line break");
+        assert!(lines[broken + 1].starts_with("https://movies.example/movie/Broken/Broken.mp4?"));
+    }
+
+    #[actix_web::test]
+    async fn xspf_escapes_xml() {
+        let out = playlist(xspf).await;
+        let doc = roxmltree::Document::parse(&out).unwrap();
+        let tracks: Vec<_> = doc
+            .descendants()
+            .filter(|n| n.has_tag_name("track"))
+            .collect();
+        assert_eq!(tracks.len(), 2);
+        let text = |track: &roxmltree::Node, tag: &str| {
+            track
+                .children()
+                .find(|n| n.has_tag_name(tag))
+                .and_then(|n| n.text())
+                .map(str::to_string)
+        };
+        let tom = tracks
+            .iter()
+            .find(|t| text(t, "title").as_deref() == Some("Tom & Jerry (1992)"))
+            .unwrap();
+        assert_eq!(text(tom, "annotation").as_deref(), Some("Cat <3 mouse"));
+        assert_eq!(text(tom, "duration").as_deref(), Some("5400000"));
+        let location = text(tom, "location").unwrap();
+        assert!(location.starts_with(
+            "https://movies.example/movie/Tom%20&%20Jerry/Tom%20&%20Jerry.mp4?profile=alice&expires="
+        ));
+        assert!(location.contains("&signature="));
+        assert!(text(tom, "image")
+            .unwrap()
+            .starts_with("https://movies.example/image/Tom%20&%20Jerry/poster%20%221%22.jpg?"));
+    }
+}
@@ -0,0 +1,244 @@
+use std::path::{Path, PathBuf};
+use std::sync::mpsc;
+use std::sync::Arc;
+use std::time::Duration;
+
+use notify::{RecommendedWatcher, RecursiveMode, Watcher};
+
+use crate::config::{Config, ConfigArgs};
+use crate::Data;
+
+/// Editors write a file in several steps, wait for them to settle.
+const DEBOUNCE: Duration = Duration::from_millis(300);
+
+/// Watches the config file and the users file and applies their changes to `data`.
+/// The returned watcher stops when dropped.
+pub fn watch(
+    data: Arc<Data>,
+    config_file: PathBuf,
+    args: ConfigArgs,
+) -> Option<RecommendedWatcher> {
+    let mut files = vec![config_file.clone()];
+    files.extend(data.config().users_file.clone());
+
+    let (tx, rx) = mpsc::channel();
+    let mut watcher = match notify::recommended_watcher(tx) {
+        Ok(watcher) => watcher,
+        Err(e) => {
+            tracing::warn!(error = %e, "not watching the config file");
+            return None;
+        }
+    };
+    // the folders, files replaced by editors would lose the watch
+    for file in &files {
+        let folder = match file.parent() {
+            Some(p) if !p.as_os_str().is_empty() => p,
+            _ => Path::new("."),
+        };
+        if let Err(e) = watcher.watch(folder, RecursiveMode::NonRecursive) {
+            tracing::warn!(file = %file.display(), error = %e, "not watching");
+        }
+    }
+
+    let names: Vec<_> = files
+        .iter()
+        .filter_map(|f| f.file_name())
+        .map(|n| n.to_owned())
+        .collect();
+    std::thread::spawn(move || {
+        while let Ok(event) = rx.recv() {
+            let relevant = event.is_ok_and(|e: notify::Event| {
+                !e.kind.is_access()
+                    && e.paths
+                        .iter()
+                        .any(|p| p.file_name().is_some_and(|n| names.iter().any(|m| m == n)))
+            });
+            if !relevant {
+                continue;
+            }
+            while rx.recv_timeout(DEBOUNCE).is_ok() {}
+            reload(&data, &config_file, &args);
+        }
+    });
+    Some(watcher)
+}
+
+fn reload(data: &Data, config_file: &Path, args: &ConfigArgs) {
+    let config = match Config::load(Some(config_file), args) {
+        Ok((config, _)) => config,
+        Err(e) => {
+            tracing::warn!(error = %e, "config not reloaded");
+            return;
+        }
+    };
+    let errors = config.validate();
+    if !errors.is_empty() {
+        for e in errors {
+            tracing::warn!("config error: {}", e);
+        }
+        tracing::warn!("config not reloaded");
+        return;
+    }
+
+    let (config, restart) = apply_live(&data.config(), config);
+    for key in restart {
+        tracing::warn!(key, "changing this needs a restart");
+    }
+
+    if let (Some(auth), Some(users_file)) = (&data.auth, &config.users_file) {
+        if let Err(e) = auth.reload(users_file) {
+            tracing::warn!(error = %e, "users not reloaded");
+        }
+    }
+    *data.config.write().unwrap() = Arc::new(config);
+    tracing::info!(file = %config_file.display(), "reloaded config");
+}
+
+/// The new config with the keys only read at startup kept at their running values,
+/// and those of them that changed.
+fn apply_live(old: &Config, mut config: Config) -> (Config, Vec<&'static str>) {
+    let mut restart = Vec::new();
+    macro_rules! keep {
+        ($($key:ident),*) => {
+            $(
+                if config.$key != old.$key {
+                    restart.push(stringify!($key));
+                    config.$key = old.$key.clone();
+                }
+            )*
+        };
+    }
+    keep!(
+        directory,
+        naming,
+        ip_bind,
+        port_bind,
+        state_file,
+        users_file,
+        tls_cert,
+        tls_key,
+        http_redirect_port,
+        signed_url_ttl,
+        log_level,
+        log_format,
+        kodi_url,
+        kodi_user,
+        kodi_password,
+        kodi_path_prefix,
+        dlna,
+        dlna_name
+    );
+    if config.access_restricted() != old.access_restricted() {
+        restart.push("auth_token");
+        config.auth_token = old.auth_token.clone();
+        config.basic_auth_user = old.basic_auth_user.clone();
+        config.basic_auth_password = old.basic_auth_password.clone();
+    }
+    (config, restart)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::auth::{hash_password, Auth};
+    use crate::config::Channel;
+    use crate::store::Store;
+    use crate::tests::test_data;
+
+    #[test]
+    fn keeps_startup_keys() {
+        let old = Config {
+            directory: "/movies/".to_string(),
+            ..Default::default()
+        };
+        let new = Config {
+            directory: "/other/".to_string(),
+            port_bind: 8080,
+            dlna: true,
+            trailer_factor: 3,
+            favorite_factor: 8,
+            max_certification: Some("FSK 12".to_string()),
+            channels: vec![Channel {
+                name: "kids".to_string(),
+                genres: Vec::new(),
+                max_certification: Some("FSK 6".to_string()),
+            }],
+            ..Default::default()
+        };
+        let (config, restart) = apply_live(&old, new);
+        assert_eq!(restart, ["directory", "port_bind", "dlna"]);
+        assert_eq!(config.directory, "/movies/");
+        assert_eq!(config.port_bind, 3070);
+        assert!(!config.dlna);
+        assert_eq!(config.trailer_factor, 3);
+        assert_eq!(config.favorite_factor, 8);
+        assert_eq!(config.max_certification.as_deref(), Some("FSK 12"));
+        assert_eq!(config.channels[0].name, "kids");
+    }
+
+    #[test]
+    fn changes_tokens_but_not_whether_access_is_restricted() {
+        let token = |t: &str| Config {
+            auth_token: Some(t.to_string()),
+            ..Default::default()
+        };
+        let (config, restart) = apply_live(&token("old"), token("new"));
+        assert!(restart.is_empty());
+        assert_eq!(config.auth_token.as_deref(), Some("new"));
+
+        let (config, restart) = apply_live(&token("old"), Config::default());
+        assert_eq!(restart, ["auth_token"]);
+        assert_eq!(config.auth_token.as_deref(), Some("old"));
+
+        let (config, restart) = apply_live(&Config::default(), token("new"));
+        assert_eq!(restart, ["auth_token"]);
+        assert_eq!(config.auth_token, None);
+    }
+
+    #[test]
+    fn reloads_the_config_and_users_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let config_file = dir.path().join("config.toml");
+        let users_file = dir.path().join("users.toml");
+        let write_config = |extra: &str| {
+            std::fs::write(
+                &config_file,
+                format!(
+                    "directory = \"{}\"\nstate_file = \"{}\"\nusers_file = \"{}\"\n{}",
+                    dir.path().display(),
+                    dir.path().join("state.json").display(),
+                    users_file.display(),
+                    extra
+                ),
+            )
+            .unwrap();
+        };
+        let hash = hash_password("secret").unwrap();
+        let user =
+            |name: &str| format!("[[users]]\nname = \"{}\"\npassword = \"{}\"\n", name, hash);
+        write_config("");
+        std::fs::write(&users_file, user("alice")).unwrap();
+
+        let args = ConfigArgs::default();
+        let (config, _) = Config::load(Some(&config_file), &args).unwrap();
+        let store = Arc::new(Store::open(config.state_file.clone()).unwrap());
+        let data = Data {
+            auth: Some(Arc::new(Auth::load(&users_file, store).unwrap())),
+            ..test_data(Vec::new(), config)
+        };
+
+        write_config("port_bind = 8080\ntrailer_factor = 5\n\n[[channels]]\nname = \"kids\"\n");
+        std::fs::write(&users_file, user("alice") + &user("bob")).unwrap();
+        reload(&data, &config_file, &args);
+        let config = data.config();
+        assert_eq!(config.trailer_factor, 5);
+        assert_eq!(config.channels.len(), 1);
+        assert_eq!(config.port_bind, 3070);
+        assert_eq!(data.auth.as_ref().unwrap().names(), ["alice", "bob"]);
+
+        // an invalid file changes nothing
+        write_config("trailer_factor = -1\n");
+        reload(&data, &config_file, &args);
+        assert_eq!(data.config().trailer_factor, 5);
+    }
+}
@@ -0,0 +1,595 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+use actix_web::{web, HttpRequest, HttpResponse, Result};
+use actix_ws::{Message, Session};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+use crate::auth::Profile;
+use crate::{
+    clearlogo_url, escape_html, find_channel, is_allowed, media_url, subtitle_tracks, Data,
+};
+
+const DEFAULT_SCREEN: &str = "tv";
+
+/// What a screen reports about itself.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ScreenStatus {
+    movie: Option<String>,
+    paused: bool,
+    channel: Option<String>,
+}
+
+struct Screen {
+    id: u64,
+    profile: String,
+    name: String,
+    session: Session,
+    status: ScreenStatus,
+}
+
+/// The `/tv` pages connected over websocket, remotes address them by profile and name.
+#[derive(Default)]
+pub struct Screens {
+    next_id: AtomicU64,
+    screens: Mutex<Vec<Screen>>,
+}
+
+impl fmt::Debug for Screens {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let screens = self.screens.lock().unwrap();
+        f.debug_list()
+            .entries(screens.iter().map(|s| (&s.profile, &s.name)))
+            .finish()
+    }
+}
+
+impl Screens {
+    fn add(&self, profile: &str, name: &str, session: Session, status: ScreenStatus) -> u64 {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        self.screens.lock().unwrap().push(Screen {
+            id,
+            profile: profile.to_string(),
+            name: name.to_string(),
+            session,
+            status,
+        });
+        id
+    }
+
+    fn remove(&self, id: u64) {
+        self.screens.lock().unwrap().retain(|s| s.id != id);
+    }
+
+    fn update(&self, id: u64, status: ScreenStatus) {
+        if let Some(screen) = self.screens.lock().unwrap().iter_mut().find(|s| s.id == id) {
+            screen.status = status;
+        }
+    }
+
+    /// Name and status of the profile's screens, a name open twice is listed once.
+    fn list(&self, profile: &str) -> BTreeMap<String, ScreenStatus> {
+        self.screens
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|s| s.profile == profile)
+            .map(|s| (s.name.clone(), s.status.clone()))
+            .collect()
+    }
+
+    /// The channel of every screen of the profile with that name.
+    fn channels(&self, profile: &str, name: &str) -> Vec<Option<String>> {
+        self.screens
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|s| s.profile == profile && s.name == name)
+            .map(|s| s.status.channel.clone())
+            .collect()
+    }
+
+    /// Sends `message` to every screen of the profile with that name, how many got it.
+    async fn send(&self, profile: &str, name: &str, message: String) -> usize {
+        let sessions: Vec<(u64, Session)> = self
+            .screens
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|s| s.profile == profile && s.name == name)
+            .map(|s| (s.id, s.session.clone()))
+            .collect();
+        let mut sent = 0;
+        for (id, mut session) in sessions {
+            if session.text(message.clone()).await.is_ok() {
+                sent += 1;
+            } else {
+                self.remove(id);
+            }
+        }
+        sent
+    }
+}
+
+fn valid_name(name: &str) -> bool {
+    !name.is_empty() && name.len() <= 64
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ScreenQuery {
+    name: Option<String>,
+    channel: Option<String>,
+}
+
+/// The websocket a `/tv` page keeps open to receive commands and report its status.
+pub async fn screen_socket(
+    req: HttpRequest,
+    body: web::Payload,
+    data: web::Data<Arc<Data>>,
+    profile: Profile,
+    query: web::Query<ScreenQuery>,
+) -> Result<HttpResponse> {
+    let name = query
+        .name
+        .clone()
+        .unwrap_or_else(|| DEFAULT_SCREEN.to_string());
+    if !valid_name(&name) {
+        return Err(actix_web::error::ErrorBadRequest("invalid screen name"));
+    }
+    let (res, mut session, mut stream) = actix_ws::handle(&req, body)?;
+    let status = ScreenStatus {
+        channel: query.channel.clone(),
+        ..Default::default()
+    };
+    let id = data.screens.add(&profile.0, &name, session.clone(), status);
+    tracing::debug!(screen = %name, profile = %profile.0, "screen connected");
+
+    let data = data.into_inner();
+    actix_web::rt::spawn(async move {
+        while let Some(Ok(message)) = stream.recv().await {
+            match message {
+                Message::Text(text) => {
+                    if let Ok(status) = serde_json::from_str(&text) {
+                        data.screens.update(id, status);
+                    }
+                }
+                Message::Ping(bytes) if session.pong(&bytes).await.is_err() => break,
+                Message::Close(_) => break,
+                _ => {}
+            }
+        }
+        data.screens.remove(id);
+        tracing::debug!(screen = %name, "screen disconnected");
+    });
+    Ok(res)
+}
+
+pub async fn list_screens(data: web::Data<Arc<Data>>, profile: Profile) -> HttpResponse {
+    let screens: Vec<serde_json::Value> = data
+        .screens
+        .list(&profile.0)
+        .into_iter()
+        .map(|(name, status)| {
+            let title = status.movie.as_ref().and_then(|movie| {
+                data.movies
+                    .iter()
+                    .find(|m| m.movie.to_string_lossy() == *movie)
+                    .map(|m| m.title())
+            });
+            json!({
+                "name": name,
+                "movie": status.movie,
+                "title": title,
+                "paused": status.paused,
+                "channel": status.channel,
+            })
+        })
+        .collect();
+    HttpResponse::Ok().json(screens)
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(tag = "command", rename_all = "snake_case")]
+pub enum RemoteCommand {
+    Next,
+    Previous,
+    /// Pauses a playing screen and resumes a paused one.
+    Pause,
+    /// No channel is the whole library.
+    SetChannel {
+        channel: Option<String>,
+    },
+    PlayMovie {
+        movie: String,
+    },
+}
+
+/// Routes a command to the profile's screens named `name`.
+pub async fn send_command(
+    data: web::Data<Arc<Data>>,
+    profile: Profile,
+    name: web::Path<String>,
+    command: web::Json<RemoteCommand>,
+) -> Result<HttpResponse> {
+    let message = match command.into_inner() {
+        RemoteCommand::Next => json!({ "command": "next" }),
+        RemoteCommand::Previous => json!({ "command": "previous" }),
+        RemoteCommand::Pause => json!({ "command": "pause" }),
+        RemoteCommand::SetChannel { channel } => {
+            if channel.is_some() && find_channel(&data, &channel).is_none() {
+                return Err(actix_web::error::ErrorBadRequest("unknown channel"));
+            }
+            json!({ "command": "set_channel", "channel": channel })
+        }
+        RemoteCommand::PlayMovie { movie } => {
+            // only what the screens' channels would play themselves
+            let channels: Vec<_> = data
+                .screens
+                .channels(&profile.0, &name)
+                .iter()
+                .map(|c| find_channel(&data, c))
+                .collect();
+            let m = data
+                .movies
+                .iter()
+                .find(|m| m.movie.to_string_lossy() == movie)
+                .filter(|m| {
+                    channels
+                        .iter()
+                        .all(|c| is_allowed(&data, &profile, c.as_ref(), m))
+                })
+                .ok_or_else(|| actix_web::error::ErrorBadRequest("unknown movie"))?;
+            json!({
+                "command": "play_movie",
+                "movie": movie,
+                "title": m.title_with_year(),
+                "logo": clearlogo_url(&data, &profile, m),
+                "src": media_url(&data, &profile, "movie", &m.movie),
+                "tracks": subtitle_tracks(&data, &profile, m),
+            })
+        }
+    };
+    let sent = data
+        .screens
+        .send(&profile.0, &name, message.to_string())
+        .await;
+    if sent == 0 {
+        return Err(actix_web::error::ErrorNotFound("no such screen"));
+    }
+    Ok(HttpResponse::Ok().json(json!({ "screens": sent })))
+}
+
+/// Connects a `/tv` player to the server, `handlers` maps commands to functions.
+pub const SCREEN_SCRIPT: &str = r#"
+function connectScreen(player, channel, handlers) {
+    const params = new URLSearchParams(location.search);
+    const name = params.get('screen') || localStorage.getItem('screen') || 'tv';
+    localStorage.setItem('screen', name);
+    let socket = null;
+    let delay = 1000;
+    function sendStatus() {
+        if (socket && socket.readyState === WebSocket.OPEN) {
+            socket.send(JSON.stringify({
+                movie: player.dataset.movie || null,
+                paused: player.paused,
+                channel: channel
+            }));
+        }
+    }
+    function connect() {
+        const query = new URLSearchParams({ name: name });
+        if (channel) {
+            query.set('channel', channel);
+        }
+        const scheme = location.protocol === 'https:' ? 'wss://' : 'ws://';
+        socket = new WebSocket(scheme + location.host + '/ws/screen?' + query);
+        socket.onopen = function() {
+            delay = 1000;
+            sendStatus();
+        };
+        socket.onmessage = function(event) {
+            const message = JSON.parse(event.data);
+            const handler = handlers[message.command];
+            if (handler) {
+                handler(message);
+            }
+        };
+        socket.onclose = function() {
+            setTimeout(connect, delay);
+            delay = Math.min(delay * 2, 30000);
+        };
+    }
+    ['playing', 'pause', 'loadeddata'].forEach(function(event) {
+        player.addEventListener(event, sendStatus);
+    });
+    connect();
+}
+"#;
+
+/// Remote control page for the profile's screens.
+pub async fn remote(data: web::Data<Arc<Data>>, profile: Profile) -> HttpResponse {
+    let channels: String = data
+        .config()
+        .channels
+        .iter()
+        .map(|c| format!(r#"<option value="{0}">{0}</option>"#, escape_html(&c.name)))
+        .collect();
+    let mut movies: Vec<_> = data
+        .movies
+        .iter()
+        .filter(|m| is_allowed(&data, &profile, None, m))
+        .collect();
+    movies.sort_by_key(|m| m.title().to_lowercase());
+    let movies: String = movies
+        .iter()
+        .map(|m| {
+            format!(
+                r#"<option value="{}">{}</option>"#,
+                escape_html(&m.movie.to_string_lossy()),
+                escape_html(&m.title_with_year())
+            )
+        })
+        .collect();
+
+    let html_content = format!(
+        r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+<meta charset="UTF-8">
+<meta name="viewport" content="width=device-width, initial-scale=1.0">
+<title>Remote</title>
+<style>
+body {{
+font-family: sans-serif;
+max-width: 30em;
+margin: 1em auto;
+padding: 0 1em;
+}}
+select, button {{
+font-size: 1.2em;
+margin: 0.2em 0;
+}}
+.controls button {{
+width: 30%;
+padding: 0.8em 0;
+}}
+#status {{
+color: #666;
+}}
+</style>
+</head>
+<body>
+<h1>Remote</h1>
+<p><select id="screen"></select></p>
+<p id="status">No screen connected, open <a href="/tv">/tv</a> on the TV.</p>
+<p class="controls">
+<button data-command="previous">&#9198;</button>
+<button data-command="pause">&#9199;</button>
+<button data-command="next">&#9197;</button>
+</p>
+<p><select id="channel"><option value="">All movies</option>{}</select>
+<button id="set-channel">Switch</button></p>
+<p><select id="movie">{}</select>
+<button id="play-movie">Play</button></p>
+<script>
+const screenSelect = document.getElementById('screen');
+const status = document.getElementById('status');
+
+function send(command) {{
+    if (!screenSelect.value) {{
+        return;
+    }}
+    fetch('/api/screens/' + encodeURIComponent(screenSelect.value), {{
+        method: 'POST',
+        headers: {{ 'Content-Type': 'application/json' }},
+        body: JSON.stringify(command)
+    }}).then(refresh);
+}}
+
+function refresh() {{
+    fetch('/api/screens').then(function(response) {{
+        return response.json();
+    }}).then(function(screens) {{
+        const selected = screenSelect.value;
+        screenSelect.innerHTML = '';
+        screens.forEach(function(screen) {{
+            const option = document.createElement('option');
+            option.value = screen.name;
+            option.textContent = screen.name;
+            screenSelect.appendChild(option);
+        }});
+        if (screens.some(function(s) {{ return s.name === selected; }})) {{
+            screenSelect.value = selected;
+        }}
+        const screen = screens.find(function(s) {{ return s.name === screenSelect.value; }});
+        if (!screen) {{
+            status.textContent = 'No screen connected, open /tv on the TV.';
+            return;
+        }}
+        status.textContent = (screen.paused ? 'Paused: ' : 'Playing: ') + (screen.title || '-')
+            + (screen.channel ? ' on ' + screen.channel : '');
+    }});
+}}
+
+document.querySelectorAll('[data-command]').forEach(function(button) {{
+    button.addEventListener('click', function() {{
+        send({{ command: button.dataset.command }});
+    }});
+}});
+document.getElementById('set-channel').addEventListener('click', function() {{
+    const channel = document.getElementById('channel').value;
+    send({{ command: 'set_channel', channel: channel || null }});
+}});
+document.getElementById('play-movie').addEventListener('click', function() {{
+    send({{ command: 'play_movie', movie: document.getElementById('movie').value }});
+}});
+screenSelect.addEventListener('change', refresh);
+refresh();
+setInterval(refresh, 3000);
+</script>
+</body>
+</html>"#,
+        channels, movies
+    );
+    HttpResponse::Ok()
+        .content_type("text/html")
+        .body(html_content)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::http::{header, StatusCode};
+    use actix_web::test::TestRequest;
+    use actix_web::FromRequest;
+
+    use crate::tests::{test_data, test_movie};
+
+    /// Adds a screen like `/ws/screen` does, its messages end up in the response body.
+    async fn connect(
+        screens: &Screens,
+        profile: &str,
+        name: &str,
+        channel: Option<&str>,
+    ) -> (u64, HttpResponse) {
+        let (req, mut payload) = TestRequest::default()
+            .insert_header((header::UPGRADE, "websocket"))
+            .insert_header((header::CONNECTION, "upgrade"))
+            .insert_header((header::SEC_WEBSOCKET_VERSION, "13"))
+            .insert_header((header::SEC_WEBSOCKET_KEY, "dGhlIHNhbXBsZSBub25jZQ=="))
+            .to_http_parts();
+        let body = web::Payload::from_request(&req, &mut payload)
+            .await
+            .unwrap();
+        let (res, session, _stream) = actix_ws::handle(&req, body).unwrap();
+        let status = ScreenStatus {
+            channel: channel.map(|c| c.to_string()),
+            ..Default::default()
+        };
+        (screens.add(profile, name, session, status), res)
+    }
+
+    /// Everything sent to a screen, once it is removed.
+    async fn received(screens: &Screens, (id, res): (u64, HttpResponse)) -> String {
+        screens.remove(id);
+        let body = actix_web::body::to_bytes(res.into_body()).await.unwrap();
+        String::from_utf8_lossy(&body).to_string()
+    }
+
+    #[test]
+    fn parses_remote_commands() {
+        let parse = |json: &str| serde_json::from_str::<RemoteCommand>(json);
+        assert!(matches!(
+            parse(r#"{"command": "next"}"#),
+            Ok(RemoteCommand::Next)
+        ));
+        assert!(matches!(
+            parse(r#"{"command": "previous"}"#),
+            Ok(RemoteCommand::Previous)
+        ));
+        assert!(matches!(
+            parse(r#"{"command": "pause"}"#),
+            Ok(RemoteCommand::Pause)
+        ));
+        assert!(matches!(
+            parse(r#"{"command": "set_channel", "channel": "kids"}"#),
+            Ok(RemoteCommand::SetChannel { channel: Some(c) }) if c == "kids"
+        ));
+        assert!(matches!(
+            parse(r#"{"command": "set_channel"}"#),
+            Ok(RemoteCommand::SetChannel { channel: None })
+        ));
+        assert!(matches!(
+            parse(r#"{"command": "play_movie", "movie": "A/A.mp4"}"#),
+            Ok(RemoteCommand::PlayMovie { movie }) if movie == "A/A.mp4"
+        ));
+        assert!(parse(r#"{"command": "play_movie"}"#).is_err());
+        assert!(parse(r#"{"command": "rewind"}"#).is_err());
+        assert!(parse(r#"{"next": true}"#).is_err());
+    }
+
+    #[actix_web::test]
+    async fn routes_to_the_profiles_screens_by_name() {
+        let screens = Screens::default();
+        let living_room = connect(&screens, "alice", "living-room", None).await;
+        let living_room_too = connect(&screens, "alice", "living-room", None).await;
+        let kitchen = connect(&screens, "alice", "kitchen", Some("kids")).await;
+        let bobs = connect(&screens, "bob", "living-room", None).await;
+
+        let names = |profile| screens.list(profile).into_keys().collect::<Vec<_>>();
+        assert_eq!(names("alice"), ["kitchen", "living-room"]);
+        assert_eq!(names("bob"), ["living-room"]);
+        assert!(names("carol").is_empty());
+        assert_eq!(
+            screens.list("alice")["kitchen"].channel.as_deref(),
+            Some("kids")
+        );
+
+        assert_eq!(
+            screens.send("alice", "living-room", "first".into()).await,
+            2
+        );
+        assert_eq!(screens.send("bob", "living-room", "second".into()).await, 1);
+        assert_eq!(screens.send("alice", "bedroom", "third".into()).await, 0);
+
+        assert!(received(&screens, living_room).await.contains("first"));
+        assert!(received(&screens, living_room_too).await.contains("first"));
+        let kitchen = received(&screens, kitchen).await;
+        assert!(!kitchen.contains("first") && !kitchen.contains("second"));
+        let bobs = received(&screens, bobs).await;
+        assert!(bobs.contains("second") && !bobs.contains("first"));
+        assert!(screens.list("alice").is_empty());
+    }
+
+    #[actix_web::test]
+    async fn plays_only_movies_of_the_screens_channel() {
+        let movie = |title: &str, certification: &str| {
+            let mut m = test_movie(title);
+            m.info.certification = Some(certification.to_string());
+            m
+        };
+        let config =
+            toml::from_str("[[channels]]\nname = \"kids\"\nmax_certification = \"FSK 6\"").unwrap();
+        let data = web::Data::new(Arc::new(test_data(
+            vec![movie("Cartoon", "FSK 0"), movie("Thriller", "FSK 16")],
+            config,
+        )));
+        let _kids = connect(&data.screens, "default", "kids", Some("kids")).await;
+        let _tv = connect(&data.screens, "default", "tv", None).await;
+
+        let play = |screen: &str, movie: &str| {
+            send_command(
+                data.clone(),
+                Profile("default".to_string()),
+                web::Path::from(screen.to_string()),
+                web::Json(RemoteCommand::PlayMovie {
+                    movie: movie.to_string(),
+                }),
+            )
+        };
+        let status = |res: Result<HttpResponse>| match res {
+            Ok(res) => res.status(),
+            Err(e) => e.as_response_error().status_code(),
+        };
+        assert_eq!(
+            status(play("kids", "Cartoon/Cartoon.mp4").await),
+            StatusCode::OK
+        );
+        assert_eq!(
+            status(play("kids", "Thriller/Thriller.mp4").await),
+            StatusCode::BAD_REQUEST
+        );
+        assert_eq!(
+            status(play("tv", "Thriller/Thriller.mp4").await),
+            StatusCode::OK
+        );
+        assert_eq!(
+            status(play("attic", "Cartoon/Cartoon.mp4").await),
+            StatusCode::NOT_FOUND
+        );
+    }
+}
@@ -0,0 +1,203 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt::Write;
+use std::path::{Path, PathBuf};
+
+use walkdir::WalkDir;
+
+use crate::naming::Naming;
+use crate::{get_folders_in_folder, striped, Movie, UNSUPPORTED_CONTAINERS};
+
+fn has_extension(path: &Path, extensions: &[&str]) -> bool {
+    path.extension()
+        .map(|e| e.to_string_lossy().to_lowercase())
+        .is_some_and(|e| extensions.contains(&e.as_str()))
+}
+
+/// Explains per library folder why movies are missing or incomplete.
+pub fn scan_report(root_dir: &String, naming: Naming, movies: &[Movie]) -> String {
+    let mut scanned: HashMap<&Path, usize> = HashMap::new();
+    let mut titles: HashMap<(String, Option<String>), Vec<&Path>> = HashMap::new();
+    for m in movies {
+        *scanned.entry(&m.movie).or_default() += 1;
+        if scanned[m.movie.as_path()] == 1 {
+            titles
+                .entry((m.title().to_lowercase(), m.info.year.clone()))
+                .or_default()
+                .push(&m.movie);
+        }
+    }
+
+    // parts, versions and extras belong to a movie without being listed on their own
+    let videos: HashSet<&Path> = movies
+        .iter()
+        .flat_map(|m| {
+            m.parts
+                .iter()
+                .chain(m.versions.iter().flat_map(|v| v.files()))
+                .chain(m.extras.iter().map(|e| &e.path))
+        })
+        .map(|p| p.as_path())
+        .collect();
+
+    let mut issues: BTreeMap<PathBuf, Vec<String>> = BTreeMap::new();
+    let mut folders = get_folders_in_folder(root_dir);
+    folders.sort();
+    for folder in folders {
+        let Some(name) = striped(root_dir, folder.clone()) else {
+            continue;
+        };
+        let folder_name = naming
+            .for_folder(&folder)
+            .movie_name(&name.to_string_lossy());
+        let mut found = Vec::new();
+        let mut unsupported = Vec::new();
+        let mut unmatched = Vec::new();
+        for entry in WalkDir::new(&folder)
+            .sort_by_file_name()
+            .into_iter()
+            .flatten()
+        {
+            if !entry.file_type().is_file() {
+                continue;
+            }
+            let Some(path) = striped(root_dir, entry.into_path()) else {
+                continue;
+            };
+            if has_extension(&path, &["mp4", "webm"]) {
+                if scanned.contains_key(path.as_path()) {
+                    found.push(path);
+                } else if videos.contains(path.as_path()) {
+                    continue;
+                } else if !path.to_string_lossy().contains("-trailer") {
+                    unmatched.push(path);
+                }
+            } else if has_extension(&path, &UNSUPPORTED_CONTAINERS) {
+                unsupported.push(path);
+            }
+        }
+
+        let mut folder_issues = Vec::new();
+        if found.is_empty() {
+            folder_issues.push(format!(
+                "no matching video, expected {0}.mp4 or {0}.webm",
+                folder_name
+            ));
+        }
+        for path in unmatched {
+            folder_issues.push(format!(
+                "name mismatch: {} does not start with {}",
+                path.display(),
+                folder_name
+            ));
+        }
+        for path in unsupported {
+            folder_issues.push(format!("unsupported container: {}", path.display()));
+        }
+        for path in &found {
+            let m = movies.iter().find(|m| &m.movie == path);
+            if m.is_some_and(|m| m.poster.is_empty() && m.thumb.is_empty() && m.fanarts.is_empty())
+            {
+                folder_issues.push(format!("no artwork: {}", path.display()));
+            }
+            if scanned[path.as_path()] > 1 {
+                folder_issues.push(format!(
+                    "scanned {} times: {}",
+                    scanned[path.as_path()],
+                    path.display()
+                ));
+            }
+            for same in titles
+                .values()
+                .filter(|p| p.len() > 1 && p.contains(&path.as_path()))
+            {
+                for other in same.iter().filter(|p| **p != path.as_path()) {
+                    folder_issues.push(format!(
+                        "duplicate movie: {} and {}",
+                        path.display(),
+                        other.display()
+                    ));
+                }
+            }
+        }
+        if !folder_issues.is_empty() {
+            issues.insert(name, folder_issues);
+        }
+    }
+
+    let mut out = String::new();
+    for (folder, folder_issues) in &issues {
+        let _ = writeln!(out, "{}/", folder.display());
+        for issue in folder_issues {
+            let _ = writeln!(out, "  {}", issue);
+        }
+    }
+    let _ = writeln!(
+        out,
+        "{} movies, {} folders with problems",
+        scanned.len(),
+        issues.len()
+    );
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn reports_each_problem_per_folder() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = format!("{}/", dir.path().display());
+        let nfo = "<movie><title>Same</title><year>2000</year></movie>";
+        for (file, content) in [
+            ("Good/Good.mp4", ""),
+            ("Good/Good-poster.jpg", ""),
+            ("Good/Good-cd1.mp4", ""),
+            ("Good/Good-cd2.mp4", ""),
+            ("Good/Good-trailer.mp4", ""),
+            ("Bare/Bare.mp4", ""),
+            ("Other/Renamed.mp4", ""),
+            ("Mkv/Mkv.mkv", ""),
+            ("Empty/notes.txt", ""),
+            ("Dup A/Dup A.mp4", ""),
+            ("Dup A/Dup A.nfo", nfo),
+            ("Dup A/Dup A-poster.jpg", ""),
+            ("Dup B/Dup B.mp4", ""),
+            ("Dup B/Dup B.nfo", nfo),
+            ("Dup B/Dup B-poster.jpg", ""),
+        ] {
+            let path = dir.path().join(file);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, content).unwrap();
+        }
+        let mut movies = crate::load_movie_data(&root, Naming::Kodi);
+        movies.sort_by(|a, b| a.movie.cmp(&b.movie));
+        // the same file listed twice
+        let bare = movies
+            .iter()
+            .find(|m| m.movie == Path::new("Bare/Bare.mp4"));
+        movies.push(bare.unwrap().clone());
+
+        assert_eq!(
+            scan_report(&root, Naming::Kodi, &movies),
+            "Bare/
+  no artwork: Bare/Bare.mp4
+  scanned 2 times: Bare/Bare.mp4
+Dup A/
+  duplicate movie: Dup A/Dup A.mp4 and Dup B/Dup B.mp4
+Dup B/
+  duplicate movie: Dup B/Dup B.mp4 and Dup A/Dup A.mp4
+Empty/
+  no matching video, expected Empty.mp4 or Empty.webm
+Mkv/
+  no matching video, expected Mkv.mp4 or Mkv.webm
+  unsupported container: Mkv/Mkv.mkv
+Other/
+  no matching video, expected Other.mp4 or Other.webm
+  name mismatch: Other/Renamed.mp4 does not start with Other
+4 movies, 6 folders with problems
+"
+        );
+    }
+}
@@ -0,0 +1,502 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+
+/// Fraction of the runtime after which a movie counts as watched.
+const WATCHED_FRACTION: f64 = 0.9;
+/// Positions below this many seconds are not worth resuming.
+const MIN_RESUME_SECONDS: f64 = 30.0;
+const MAX_HISTORY: usize = 1000;
+/// Changes are written at most this often, players report progress every few seconds.
+const SAVE_INTERVAL: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Progress {
+    pub position: f64,
+    pub duration: f64,
+    pub updated: u64,
+    pub watched: bool,
+}
+
+impl Progress {
+    /// Position to continue from, if the movie was started but not finished.
+    pub fn resume_position(&self) -> Option<f64> {
+        if self.position >= MIN_RESUME_SECONDS
+            && self.duration > 0.0
+            && self.position / self.duration < WATCHED_FRACTION
+        {
+            Some(self.position)
+        } else {
+            None
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HistoryEntry {
+    pub movie: String,
+    pub time: u64,
+}
+
+/// The user's own opinion about a movie.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Flags {
+    pub favorite: bool,
+    pub disliked: bool,
+    pub hidden: bool,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct FlagsUpdate {
+    pub favorite: Option<bool>,
+    pub disliked: Option<bool>,
+    pub hidden: Option<bool>,
+}
+
+/// Everything recorded for one user.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+struct ProfileState {
+    #[serde(default)]
+    progress: BTreeMap<String, Progress>,
+    #[serde(default)]
+    history: Vec<HistoryEntry>,
+    #[serde(default)]
+    flags: BTreeMap<String, Flags>,
+}
+
+/// A login of the users file, so it survives restarts.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Session {
+    user: String,
+    /// Unix time.
+    expires: u64,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+struct State {
+    #[serde(default)]
+    profiles: BTreeMap<String, ProfileState>,
+    /// Keyed by a hash of the session cookie, the file doesn't hold valid cookies.
+    #[serde(default)]
+    sessions: BTreeMap<String, Session>,
+}
+
+/// Watch progress, history and flags per profile and the login sessions, persisted as json by
+/// [`Store::spawn_writer`] so requests don't wait for the disk.
+#[derive(Debug)]
+pub struct Store {
+    path: PathBuf,
+    state: Mutex<State>,
+    /// Changed since the last write.
+    dirty: AtomicBool,
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+impl Store {
+    pub fn default_path() -> Option<PathBuf> {
+        dirs::data_dir().map(|p| p.join("random_video_server").join("state.json"))
+    }
+
+    pub fn open(path: PathBuf) -> io::Result<Store> {
+        let state = match fs::read_to_string(&path) {
+            Ok(content) => serde_json::from_str(&content).map_err(|e| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("{}: {}", path.display(), e),
+                )
+            })?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => State::default(),
+            Err(e) => return Err(e),
+        };
+        Ok(Store {
+            path,
+            state: Mutex::new(state),
+            dirty: AtomicBool::new(false),
+        })
+    }
+
+    fn changed(&self) {
+        self.dirty.store(true, Ordering::Release);
+    }
+
+    /// Writes the state if it changed since the last write.
+    pub fn flush(&self) -> io::Result<()> {
+        if !self.dirty.swap(false, Ordering::AcqRel) {
+            return Ok(());
+        }
+        // the lock is only held while serializing, not while writing
+        let json = serde_json::to_string(&*self.state.lock().unwrap())?;
+        let result = self.write(&json);
+        if result.is_err() {
+            self.changed();
+        }
+        result
+    }
+
+    fn write(&self, json: &str) -> io::Result<()> {
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let tmp = self.path.with_extension("json.tmp");
+        fs::write(&tmp, json)?;
+        fs::rename(tmp, &self.path)
+    }
+
+    /// Writes changes every [`SAVE_INTERVAL`] on its own thread.
+    pub fn spawn_writer(store: Arc<Store>) {
+        std::thread::spawn(move || loop {
+            std::thread::sleep(SAVE_INTERVAL);
+            if let Err(e) = store.flush() {
+                tracing::warn!(path = %store.path.display(), error = %e, "state not saved");
+            }
+        });
+    }
+
+    pub fn record_progress(&self, profile: &str, movie: &str, position: f64, duration: f64) {
+        let mut state = self.state.lock().unwrap();
+        let profile_state = state.profiles.entry(profile.to_string()).or_default();
+        let progress = profile_state.progress.entry(movie.to_string()).or_default();
+        progress.position = position;
+        progress.duration = duration;
+        progress.updated = now();
+        if duration > 0.0 && position / duration >= WATCHED_FRACTION {
+            progress.watched = true;
+        }
+        self.changed();
+    }
+
+    /// Takes over progress from another player for movies this profile hasn't
+    /// played here yet.
+    pub fn import_progress(&self, profile: &str, progress: &[(String, Progress)]) {
+        let mut state = self.state.lock().unwrap();
+        let profile_state = state.profiles.entry(profile.to_string()).or_default();
+        let mut changed = false;
+        for (movie, p) in progress {
+            if !profile_state.progress.contains_key(movie) {
+                profile_state.progress.insert(
+                    movie.clone(),
+                    Progress {
+                        updated: now(),
+                        ..p.clone()
+                    },
+                );
+                changed = true;
+            }
+        }
+        if changed {
+            self.changed();
+        }
+    }
+
+    pub fn record_played(&self, profile: &str, movie: &str) {
+        let mut state = self.state.lock().unwrap();
+        let history = &mut state
+            .profiles
+            .entry(profile.to_string())
+            .or_default()
+            .history;
+        history.push(HistoryEntry {
+            movie: movie.to_string(),
+            time: now(),
+        });
+        let overflow = history.len().saturating_sub(MAX_HISTORY);
+        history.drain(..overflow);
+        self.changed();
+    }
+
+    pub fn progress(&self, profile: &str, movie: &str) -> Option<Progress> {
+        let state = self.state.lock().unwrap();
+        state.profiles.get(profile)?.progress.get(movie).cloned()
+    }
+
+    pub fn is_watched(&self, profile: &str, movie: &str) -> bool {
+        self.progress(profile, movie).is_some_and(|p| p.watched)
+    }
+
+    /// Started but unfinished movies, most recently watched first.
+    pub fn continue_watching(&self, profile: &str) -> Vec<(String, Progress)> {
+        let state = self.state.lock().unwrap();
+        let mut ret: Vec<(String, Progress)> = state
+            .profiles
+            .get(profile)
+            .iter()
+            .flat_map(|p| p.progress.iter())
+            .filter(|(_, p)| p.resume_position().is_some())
+            .map(|(m, p)| (m.clone(), p.clone()))
+            .collect();
+        ret.sort_by_key(|(_, p)| std::cmp::Reverse(p.updated));
+        ret
+    }
+
+    pub fn flags(&self, profile: &str, movie: &str) -> Flags {
+        let state = self.state.lock().unwrap();
+        state
+            .profiles
+            .get(profile)
+            .and_then(|p| p.flags.get(movie))
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    pub fn update_flags(&self, profile: &str, movie: &str, update: &FlagsUpdate) -> Flags {
+        let mut state = self.state.lock().unwrap();
+        let profile_state = state.profiles.entry(profile.to_string()).or_default();
+        let flags = profile_state.flags.entry(movie.to_string()).or_default();
+        if let Some(favorite) = update.favorite {
+            flags.favorite = favorite;
+        }
+        if let Some(disliked) = update.disliked {
+            flags.disliked = disliked;
+        }
+        if let Some(hidden) = update.hidden {
+            flags.hidden = hidden;
+        }
+        let flags = flags.clone();
+        if !flags.favorite && !flags.disliked && !flags.hidden {
+            profile_state.flags.remove(movie);
+        }
+        self.changed();
+        flags
+    }
+
+    /// Most recent first.
+    pub fn history(&self, profile: &str) -> Vec<HistoryEntry> {
+        let state = self.state.lock().unwrap();
+        state
+            .profiles
+            .get(profile)
+            .map(|p| p.history.iter().rev().cloned().collect())
+            .unwrap_or_default()
+    }
+
+    pub fn add_session(&self, key: &str, user: &str, expires: u64) {
+        let mut state = self.state.lock().unwrap();
+        state.sessions.insert(
+            key.to_string(),
+            Session {
+                user: user.to_string(),
+                expires,
+            },
+        );
+        self.changed();
+    }
+
+    /// The user of a session that hasn't expired.
+    pub fn session_user(&self, key: &str) -> Option<String> {
+        let mut state = self.state.lock().unwrap();
+        let now = now();
+        let before = state.sessions.len();
+        state.sessions.retain(|_, s| s.expires > now);
+        if state.sessions.len() != before {
+            self.changed();
+        }
+        state.sessions.get(key).map(|s| s.user.clone())
+    }
+
+    pub fn remove_session(&self, key: &str) {
+        if self.state.lock().unwrap().sessions.remove(key).is_some() {
+            self.changed();
+        }
+    }
+
+    /// Ends the sessions of the users `keep` rejects.
+    pub fn retain_sessions(&self, keep: impl Fn(&str) -> bool) {
+        let mut state = self.state.lock().unwrap();
+        let before = state.sessions.len();
+        state.sessions.retain(|_, s| keep(&s.user));
+        if state.sessions.len() != before {
+            self.changed();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn open(dir: &tempfile::TempDir) -> Store {
+        Store::open(dir.path().join("state").join("state.json")).unwrap()
+    }
+
+    #[test]
+    fn watched_after_most_of_the_runtime() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = open(&dir);
+        store.record_progress("alice", "A/A.mp4", 600.0, 1000.0);
+        let progress = store.progress("alice", "A/A.mp4").unwrap();
+        assert!(!progress.watched);
+        assert_eq!(progress.resume_position(), Some(600.0));
+
+        store.record_progress("alice", "A/A.mp4", 900.0, 1000.0);
+        assert!(store.is_watched("alice", "A/A.mp4"));
+        assert_eq!(
+            store
+                .progress("alice", "A/A.mp4")
+                .unwrap()
+                .resume_position(),
+            None
+        );
+        // rewinding a watched movie doesn't make it unwatched
+        store.record_progress("alice", "A/A.mp4", 100.0, 1000.0);
+        assert!(store.is_watched("alice", "A/A.mp4"));
+        assert!(!store.is_watched("bob", "A/A.mp4"));
+    }
+
+    #[test]
+    fn continues_the_latest_started_movie_first() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = open(&dir);
+        store.record_progress("alice", "A/A.mp4", 100.0, 1000.0);
+        store.record_progress("alice", "B/B.mp4", 100.0, 1000.0);
+        store.record_progress("alice", "C/C.mp4", 10.0, 1000.0);
+        store.record_progress("alice", "D/D.mp4", 950.0, 1000.0);
+        for (movie, updated) in [("A/A.mp4", 2), ("B/B.mp4", 1)] {
+            let mut state = store.state.lock().unwrap();
+            state
+                .profiles
+                .get_mut("alice")
+                .unwrap()
+                .progress
+                .get_mut(movie)
+                .unwrap()
+                .updated = updated;
+        }
+
+        let movies: Vec<String> = store
+            .continue_watching("alice")
+            .into_iter()
+            .map(|(m, _)| m)
+            .collect();
+        // barely started and finished movies are left out
+        assert_eq!(movies, ["A/A.mp4", "B/B.mp4"]);
+        assert!(store.continue_watching("bob").is_empty());
+    }
+
+    #[test]
+    fn keeps_the_latest_history() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = open(&dir);
+        for i in 0..MAX_HISTORY + 5 {
+            store.record_played("alice", &format!("{}", i));
+        }
+        let history = store.history("alice");
+        assert_eq!(history.len(), MAX_HISTORY);
+        assert_eq!(history[0].movie, format!("{}", MAX_HISTORY + 4));
+        assert_eq!(history[MAX_HISTORY - 1].movie, "5");
+    }
+
+    #[test]
+    fn imports_only_unknown_progress() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = open(&dir);
+        store.record_progress("alice", "A/A.mp4", 100.0, 1000.0);
+        let kodi = |position| Progress {
+            position,
+            duration: 1000.0,
+            updated: 0,
+            watched: false,
+        };
+        store.import_progress(
+            "alice",
+            &[
+                ("A/A.mp4".to_string(), kodi(500.0)),
+                ("B/B.mp4".to_string(), kodi(500.0)),
+            ],
+        );
+        assert_eq!(store.progress("alice", "A/A.mp4").unwrap().position, 100.0);
+        assert_eq!(store.progress("alice", "B/B.mp4").unwrap().position, 500.0);
+    }
+
+    #[test]
+    fn clearing_all_flags_removes_the_entry() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = open(&dir);
+        let flags = store.update_flags(
+            "alice",
+            "A/A.mp4",
+            &FlagsUpdate {
+                favorite: Some(true),
+                ..Default::default()
+            },
+        );
+        assert!(flags.favorite && !flags.hidden);
+        let flags = store.update_flags(
+            "alice",
+            "A/A.mp4",
+            &FlagsUpdate {
+                hidden: Some(true),
+                ..Default::default()
+            },
+        );
+        assert!(flags.favorite && flags.hidden);
+        assert!(!store.flags("bob", "A/A.mp4").favorite);
+
+        store.update_flags(
+            "alice",
+            "A/A.mp4",
+            &FlagsUpdate {
+                favorite: Some(false),
+                hidden: Some(false),
+                ..Default::default()
+            },
+        );
+        let state = store.state.lock().unwrap();
+        assert!(state.profiles["alice"].flags.is_empty());
+    }
+
+    #[test]
+    fn sessions_expire() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = open(&dir);
+        store.add_session("old", "alice", now() - 1);
+        store.add_session("new", "alice", now() + 60);
+        assert_eq!(store.session_user("old"), None);
+        assert_eq!(store.session_user("new").as_deref(), Some("alice"));
+        assert_eq!(store.state.lock().unwrap().sessions.len(), 1);
+    }
+
+    #[test]
+    fn flushes_changes_and_reopens_them() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = open(&dir);
+        // nothing changed, nothing written
+        store.flush().unwrap();
+        assert!(!store.path.exists());
+
+        store.record_progress("alice", "A/A.mp4", 100.0, 1000.0);
+        store.record_played("alice", "A/A.mp4");
+        store.flush().unwrap();
+        assert!(store.path.exists());
+        assert!(!store.path.with_extension("json.tmp").exists());
+
+        let reopened = open(&dir);
+        assert_eq!(
+            reopened.progress("alice", "A/A.mp4").unwrap().position,
+            100.0
+        );
+        assert_eq!(reopened.history("alice").len(), 1);
+    }
+
+    #[test]
+    fn rejects_a_broken_state_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("state.json");
+        fs::write(&path, "{").unwrap();
+        let error = Store::open(path).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        assert!(error.to_string().contains("state.json"));
+    }
+}
@@ -0,0 +1,240 @@
+use std::path::{Path, PathBuf};
+
+use crate::{remove_extension, try_files};
+
+#[derive(Debug, Clone)]
+pub struct Subtitle {
+    pub path: PathBuf,
+    pub language: Option<String>,
+    pub label: String,
+}
+
+const SUBTITLE_EXTENSIONS: [&str; 4] = ["srt", "vtt", "ass", "ssa"];
+
+/// Finds `<name>.srt`, `<name>.en.srt`, `<name>.forced.de.ass`, ... next to the video.
+pub fn find_subtitles(root_dir: &String, video: &Path) -> Vec<Subtitle> {
+    let base = remove_extension(video);
+    let stem = video
+        .file_stem()
+        .map(|s| s.to_string_lossy().to_string())
+        .unwrap_or_default();
+
+    let base = glob::Pattern::escape(&base);
+    let mut ret = Vec::new();
+    for path in try_files(
+        root_dir,
+        SUBTITLE_EXTENSIONS
+            .iter()
+            // not `<name>*`, that would also find the subtitles of `<name> 2`
+            .flat_map(|ext| [format!("{}.{}", base, ext), format!("{}.*.{}", base, ext)])
+            .collect(),
+    ) {
+        let file_name = path
+            .file_name()
+            .map(|s| s.to_string_lossy().to_string())
+            .unwrap_or_default();
+        // strip "<name>" and ".<ext>" leaving e.g. ".forced.en"
+        let tags: Vec<String> = file_name
+            .strip_prefix(&stem)
+            .and_then(|rest| rest.rsplit_once('.').map(|(tags, _ext)| tags))
+            .unwrap_or("")
+            .split(['.', '-', '_', ' '])
+            .filter(|t| !t.is_empty())
+            .map(|t| t.to_string())
+            .collect();
+        let language = tags
+            .iter()
+            .find(|t| (2..=3).contains(&t.len()) && t.chars().all(|c| c.is_ascii_alphabetic()))
+            .map(|t| t.to_lowercase());
+        let label = if tags.is_empty() {
+            "Default".to_string()
+        } else {
+            tags.join(" ")
+        };
+        ret.push(Subtitle {
+            path,
+            language,
+            label,
+        });
+    }
+    ret
+}
+
+/// Converts the content of a subtitle file to WebVTT based on its extension.
+pub fn to_webvtt(path: &Path, content: &str) -> String {
+    let content = content.trim_start_matches('\u{feff}').replace("\r\n", "\n");
+    match path
+        .extension()
+        .map(|e| e.to_string_lossy().to_lowercase())
+        .as_deref()
+    {
+        Some("vtt") => content,
+        Some("ass") | Some("ssa") => ass_to_webvtt(&content),
+        _ => srt_to_webvtt(&content),
+    }
+}
+
+fn srt_to_webvtt(content: &str) -> String {
+    let mut ret = String::from("WEBVTT\n\n");
+    for line in content.lines() {
+        if line.contains("-->") {
+            // 00:00:01,000 --> 00:00:02,000
+            ret.push_str(&line.replace(',', "."));
+        } else {
+            ret.push_str(line);
+        }
+        ret.push('\n');
+    }
+    ret
+}
+
+fn ass_time_to_webvtt(time: &str) -> Option<String> {
+    // H:MM:SS.cc
+    let (h, rest) = time.trim().split_once(':')?;
+    let (m, rest) = rest.split_once(':')?;
+    let (s, cs) = rest.split_once('.')?;
+    let h: u32 = h.parse().ok()?;
+    let m: u32 = m.parse().ok()?;
+    let s: u32 = s.parse().ok()?;
+    let cs: u32 = cs.parse().ok()?;
+    Some(format!("{:02}:{:02}:{:02}.{:03}", h, m, s, cs * 10))
+}
+
+fn ass_text_to_webvtt(text: &str) -> String {
+    let mut ret = String::new();
+    let mut in_override = false;
+    for c in text.chars() {
+        match c {
+            '{' => in_override = true,
+            '}' => in_override = false,
+            _ if !in_override => ret.push(c),
+            _ => {}
+        }
+    }
+    ret.replace("\\N", "\n")
+        .replace("\\n", "\n")
+        .replace("\\h", " ")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+fn ass_to_webvtt(content: &str) -> String {
+    let mut ret = String::from("WEBVTT\n\n");
+    let mut in_events = false;
+    let mut format: Vec<String> = Vec::new();
+    for line in content.lines() {
+        let line = line.trim();
+        if line.starts_with('[') {
+            in_events = line.eq_ignore_ascii_case("[events]");
+            continue;
+        }
+        if !in_events {
+            continue;
+        }
+        if let Some(f) = line.strip_prefix("Format:") {
+            format = f.split(',').map(|c| c.trim().to_lowercase()).collect();
+        } else if let Some(d) = line.strip_prefix("Dialogue:") {
+            // the text column is always the last one and may contain commas
+            let fields: Vec<&str> = d.splitn(format.len().max(1), ',').collect();
+            let column = |name: &str| {
+                format
+                    .iter()
+                    .position(|c| c == name)
+                    .and_then(|i| fields.get(i))
+            };
+            if let (Some(start), Some(end), Some(text)) =
+                (column("start"), column("end"), column("text"))
+            {
+                if let (Some(start), Some(end)) =
+                    (ass_time_to_webvtt(start), ass_time_to_webvtt(end))
+                {
+                    ret.push_str(&format!(
+                        "{} --> {}\n{}\n\n",
+                        start,
+                        end,
+                        ass_text_to_webvtt(text)
+                    ));
+                }
+            }
+        }
+    }
+    ret
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn finds_only_subtitles_of_the_video() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = format!("{}/", dir.path().display());
+        let folder = dir.path().join("Movie");
+        fs::create_dir_all(&folder).unwrap();
+        for file in [
+            "Movie.mp4",
+            "Movie.srt",
+            "Movie.en.srt",
+            "Movie.forced.de.ass",
+            "Movie 2.srt",
+            "Movie 2.en.srt",
+            "Movies.vtt",
+        ] {
+            fs::write(folder.join(file), "").unwrap();
+        }
+
+        let mut found: Vec<(String, Option<String>, String)> =
+            find_subtitles(&root, &folder.join("Movie.mp4"))
+                .into_iter()
+                .map(|s| (s.path.display().to_string(), s.language, s.label))
+                .collect();
+        found.sort();
+        assert_eq!(
+            found,
+            vec![
+                (
+                    "Movie/Movie.en.srt".to_string(),
+                    Some("en".to_string()),
+                    "en".to_string()
+                ),
+                (
+                    "Movie/Movie.forced.de.ass".to_string(),
+                    Some("de".to_string()),
+                    "forced de".to_string()
+                ),
+                ("Movie/Movie.srt".to_string(), None, "Default".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn converts_srt() {
+        let srt = "\u{feff}1\r\n00:00:01,000 --> 00:00:02,500\r\nHello, world\r\n\r\n";
+        assert_eq!(
+            to_webvtt(Path::new("Movie.srt"), srt),
+            "WEBVTT\n\n1\n00:00:01.000 --> 00:00:02.500\nHello, world\n\n"
+        );
+    }
+
+    #[test]
+    fn passes_vtt_through() {
+        let vtt = "WEBVTT\n\n00:00:01.000 --> 00:00:02.000\nHello, world\n";
+        assert_eq!(to_webvtt(Path::new("Movie.en.vtt"), vtt), vtt);
+        assert_eq!(
+            to_webvtt(Path::new("Movie.VTT"), &format!("\u{feff}{}", vtt)),
+            vtt
+        );
+    }
+
+    #[test]
+    fn converts_ass() {
+        let ass = "[Script Info]\nTitle: x\n\n[Events]\n\
+                   Format: Layer, Start, End, Style, Text\n\
+                   Dialogue: 0,0:00:01.50,0:00:03.00,Default,{\\i1}Hello,\\Nworld\n";
+        assert_eq!(
+            to_webvtt(Path::new("Movie.ass"), ass),
+            "WEBVTT\n\n00:00:01.500 --> 00:00:03.000\nHello,\nworld\n\n"
+        );
+    }
+}
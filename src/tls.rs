@@ -0,0 +1,46 @@
+use std::io;
+use std::path::Path;
+
+use actix_web::http::header;
+use actix_web::{web, HttpRequest, HttpResponse, Responder};
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use rustls::ServerConfig;
+
+fn pem_error(path: &Path, e: impl std::fmt::Display) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("{}: {}", path.display(), e),
+    )
+}
+
+/// Reads a PEM certificate chain and private key.
+pub fn load_server_config(cert: &Path, key: &Path) -> io::Result<ServerConfig> {
+    let certs = CertificateDer::pem_file_iter(cert)
+        .map_err(|e| pem_error(cert, e))?
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| pem_error(cert, e))?;
+    let key = PrivateKeyDer::from_pem_file(key).map_err(|e| pem_error(key, e))?;
+
+    ServerConfig::builder_with_provider(rustls::crypto::ring::default_provider().into())
+        .with_safe_default_protocol_versions()
+        .and_then(|builder| builder.with_no_client_auth().with_single_cert(certs, key))
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e.to_string()))
+}
+
+/// Sends plain http requests to the same path on the https port.
+pub async fn redirect_to_https(req: HttpRequest, https_port: web::Data<u16>) -> impl Responder {
+    let info = req.connection_info();
+    let host = info.host();
+    // strip the port of the http listener, keep ipv6 brackets
+    let host = match host.rsplit_once(':') {
+        Some((h, port)) if !port.contains(']') => h,
+        _ => host,
+    };
+    HttpResponse::PermanentRedirect()
+        .insert_header((
+            header::LOCATION,
+            format!("https://{}:{}{}", host, https_port.get_ref(), req.uri()),
+        ))
+        .finish()
+}